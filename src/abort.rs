@@ -5,7 +5,7 @@ use {
         *,
     },
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
+type Res<T> = Result<T, crate::err::Error>;
 static ABORT: OnceLock<Arc<RwLock<bool>>> = OnceLock::new();
 pub fn get() -> Res<bool> {
     let a = ABORT.get_or_init(|| init_internal().unwrap());
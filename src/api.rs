@@ -0,0 +1,212 @@
+use {
+    crate::{config, e, error, info, metrics, platforms::Platform, s, status},
+    std::{
+        io::{BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+        path::Path,
+        *,
+    },
+};
+type Res<T> = Result<T, crate::err::Error>;
+/// serializes edits of the config file
+static CONFIG_EDIT: sync::Mutex<()> = sync::Mutex::new(());
+/// starts the http api in the background
+/// changes to models are written to the config file and picked up by the main loop
+/// requests that add, remove or stop models need the token if one is set
+pub fn start(address: &str, token: Option<String>, config_path: &Path) -> Res<()> {
+    let listener = TcpListener::bind(address).map_err(e!())?;
+    info!("api listening on {}", address);
+    let config_path = config_path.to_path_buf();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(r) => r,
+                Err(e) => {
+                    error!("{}", e);
+                    continue;
+                }
+            };
+            let config_path = config_path.clone();
+            let token = token.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle(stream, token.as_deref(), &config_path) {
+                    error!("{}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+impl Response {
+    fn json(status: u16, value: serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: value.to_string(),
+        }
+    }
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, serde_json::json!({ "error": message }))
+    }
+}
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    }
+}
+/// reads one request and writes its response
+fn handle(mut stream: TcpStream, token: Option<&str>, config_path: &Path) -> Res<()> {
+    stream
+        .set_read_timeout(Some(time::Duration::from_secs(5)))
+        .map_err(e!())?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).map_err(e!())?;
+    // only the authorization header is used, bodies are not accepted
+    let mut authorization = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(e!())? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("authorization")
+        {
+            authorization = Some(value.trim().to_string());
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let path = target.split('?').next().unwrap_or_default();
+    let authorized = match token {
+        Some(token) => authorization
+            .as_deref()
+            .and_then(|a| a.strip_prefix("Bearer "))
+            .is_some_and(|a| a.trim() == token),
+        None => true,
+    };
+    let response = match route(method, path, authorized, config_path) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("{}", e);
+            Response::error(500, &e.to_string())
+        }
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+        response.body
+    )
+    .map_err(e!())?;
+    Ok(())
+}
+/// decodes %XX escapes of a path segment, none if they are invalid
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+fn route(method: &str, path: &str, authorized: bool, config_path: &Path) -> Res<Response> {
+    // split before decoding so an escaped "/" stays in its segment
+    let decoded: Option<Vec<String>> = path
+        .trim_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect();
+    let Some(decoded) = decoded else {
+        return Ok(Response::error(400, "invalid path"));
+    };
+    let segments: Vec<&str> = decoded.iter().map(String::as_str).collect();
+    if method != "GET" && !authorized {
+        return Ok(Response::error(401, "invalid or missing token"));
+    }
+    let response = match (method, segments.as_slice()) {
+        ("GET", ["models"]) => {
+            let list = status::list().map_err(s!())?;
+            Response::json(200, serde_json::to_value(list).map_err(e!())?)
+        }
+        (method, ["models", platform, username, rest @ ..]) => {
+            let platform = match Platform::new(&platform.to_uppercase()) {
+                Some(o) => o,
+                None => return Ok(Response::error(400, "unknown platform")),
+            };
+            model_route(method, rest, platform, username, config_path).map_err(s!())?
+        }
+        ("GET", ["metrics"]) => Response {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body: metrics::render().map_err(s!())?,
+        },
+        (_, ["models" | "metrics"]) => Response::error(405, "method not allowed"),
+        _ => Response::error(404, "not found"),
+    };
+    Ok(response)
+}
+fn model_route(
+    method: &str,
+    rest: &[&str],
+    platform: Platform,
+    username: &str,
+    config_path: &Path,
+) -> Res<Response> {
+    let key = status::key(&platform, username);
+    let response = match (method, rest) {
+        ("GET", []) => match status::get(&key).map_err(s!())? {
+            Some(status) => Response::json(200, serde_json::to_value(status).map_err(e!())?),
+            None => Response::error(404, "model not tracked"),
+        },
+        ("POST", []) => {
+            let _edit = CONFIG_EDIT.lock().map_err(s!())?;
+            let mut config = config::init(config_path).map_err(s!())?;
+            if !config.model_add(platform, username) {
+                return Ok(Response::error(409, "model already tracked"));
+            }
+            config.write().map_err(s!())?;
+            Response::json(201, serde_json::json!({ "added": key }))
+        }
+        ("DELETE", []) => {
+            let _edit = CONFIG_EDIT.lock().map_err(s!())?;
+            let mut config = config::init(config_path).map_err(s!())?;
+            if !config.model_remove(&platform, username) {
+                return Ok(Response::error(404, "model not tracked"));
+            }
+            config.write().map_err(s!())?;
+            Response::json(200, serde_json::json!({ "removed": key }))
+        }
+        ("POST", ["stop"]) => match status::stop(&key).map_err(s!())? {
+            true => Response::json(200, serde_json::json!({ "stopped": key })),
+            false => Response::error(409, "model not recording"),
+        },
+        (_, [] | ["stop"]) => Response::error(405, "method not allowed"),
+        _ => Response::error(404, "not found"),
+    };
+    Ok(response)
+}
@@ -0,0 +1,196 @@
+use {
+    crate::{
+        abort, config, history, journal, log,
+        platforms::{Model, ModelOptions, Platform},
+        s, stream,
+    },
+    std::{ffi::OsString, path::PathBuf, *},
+};
+type Res<T> = Result<T, crate::err::Error>;
+pub const USAGE: &str = "usage: cbstream [--config <path>] [command]
+
+commands:
+  (none)                            record every model in the config
+  add <platform> <user>             adds a model to the config
+  remove <platform> <user>          removes a model from the config
+  list                              lists the models in the config
+  check <platform> <user>           prints the room state of a model and its playlist
+  record <platform> <user> [--once] records a model, --once stops after one session
+
+platforms: CB, SC, SCVR, MFC, BONGA, SODA, HLS";
+pub enum Command {
+    /// records every model in the config
+    Run,
+    Add(Platform, String),
+    Remove(Platform, String),
+    List,
+    Check(Platform, String),
+    Record {
+        platform: Platform,
+        username: String,
+        once: bool,
+    },
+    Help,
+}
+pub struct Args {
+    pub config: PathBuf,
+    pub command: Command,
+}
+/// parses the command line, a lone argument that is not a command is the config path
+pub fn parse(args: impl Iterator<Item = OsString>) -> Res<Args> {
+    let mut config: Option<PathBuf> = None;
+    let mut once = false;
+    let mut positional: Vec<OsString> = Vec::new();
+    let mut args = args.skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-c" | "--config") => {
+                config = Some(args.next().ok_or("--config needs a path")?.into());
+            }
+            Some("--once") => once = true,
+            Some("-h" | "--help") => positional.insert(0, "help".into()),
+            _ => positional.push(arg),
+        }
+    }
+    let strings: Vec<&str> = positional.iter().filter_map(|a| a.to_str()).collect();
+    let command = match strings.as_slice() {
+        [] => Command::Run,
+        ["help", ..] => Command::Help,
+        ["add", platform, username] => Command::Add(platform_arg(platform)?, username.to_string()),
+        ["remove", platform, username] => {
+            Command::Remove(platform_arg(platform)?, username.to_string())
+        }
+        ["list"] => Command::List,
+        ["check", platform, username] => {
+            Command::Check(platform_arg(platform)?, username.to_string())
+        }
+        ["record", platform, username] => Command::Record {
+            platform: platform_arg(platform)?,
+            username: username.to_string(),
+            once,
+        },
+        [command, ..] if is_command(command) => {
+            return Err(format!("wrong arguments for {}", command).into());
+        }
+        _ if positional.len() == 1 && config.is_none() => {
+            config = positional.pop().map(PathBuf::from);
+            Command::Run
+        }
+        _ => return Err("unknown command".into()),
+    };
+    let config = config
+        .or_else(|| env::var_os("CONFIG").map(PathBuf::from))
+        .unwrap_or_else(|| "cb-config.json".into());
+    Ok(Args { config, command })
+}
+fn is_command(arg: &str) -> bool {
+    matches!(arg, "add" | "remove" | "list" | "check" | "record")
+}
+fn platform_arg(arg: &str) -> Res<Platform> {
+    Platform::new(&arg.to_uppercase()).ok_or_else(|| format!("unknown platform {}", arg).into())
+}
+/// runs a command other than Run
+pub fn run(config_path: &path::Path, command: Command) -> Res<()> {
+    match command {
+        Command::Run => Err("run is handled by main".into()),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Command::Add(platform, username) => {
+            let mut config = config::init(config_path).map_err(s!())?;
+            if config.model_add(platform.clone(), &username) {
+                config.write().map_err(s!())?;
+                println!("added {:?}:{}", platform, username);
+            } else {
+                println!("{:?}:{} is already in the config", platform, username);
+            }
+            Ok(())
+        }
+        Command::Remove(platform, username) => {
+            let mut config = config::init(config_path).map_err(s!())?;
+            if config.model_remove(&platform, &username) {
+                config.write().map_err(s!())?;
+                println!("removed {:?}:{}", platform, username);
+            } else {
+                println!("{:?}:{} is not in the config", platform, username);
+            }
+            Ok(())
+        }
+        Command::List => {
+            let config = config::init(config_path).map_err(s!())?;
+            for model in config.models() {
+                let mut notes = Vec::new();
+                if !model.options.enabled {
+                    notes.push("disabled".to_string());
+                }
+                if let Some(quality) = model.options.quality.as_ref() {
+                    notes.push(quality.clone());
+                }
+                if let Some(output) = model.options.output.as_ref() {
+                    notes.push(output.display().to_string());
+                }
+                match notes.is_empty() {
+                    true => println!("{}", model.composite_key()),
+                    false => println!("{} ({})", model.composite_key(), notes.join(", ")),
+                }
+            }
+            Ok(())
+        }
+        Command::Check(platform, username) => {
+            let config = config::init(config_path).map_err(s!())?;
+            let mut model = config_model(&config, platform, &username);
+            match model.check(config.settings()) {
+                Some(room) => {
+                    println!("{} is {}", model.composite_key(), room.state);
+                    for playlist in [room.playlist_url, room.playlist_audio_url]
+                        .into_iter()
+                        .flatten()
+                    {
+                        println!("{}", playlist);
+                    }
+                }
+                None => println!("{} could not be checked", model.composite_key()),
+            }
+            Ok(())
+        }
+        Command::Record {
+            platform,
+            username,
+            once,
+        } => {
+            let config = config::init(config_path).map_err(s!())?;
+            let settings = config.settings();
+            log::configure(&settings.log);
+            stream::memory_budget()
+                .set_limit(settings.memory_budget)
+                .map_err(s!())?;
+            journal::recover().map_err(s!())?;
+            history::load(&history::path(config_path)).map_err(s!())?;
+            let mut model = config_model(&config, platform, &username);
+            model.options.enabled = true;
+            while !abort::get().map_err(s!())? {
+                model.download(settings.clone(), None).map_err(s!())?;
+                if model.is_downloading().map_err(s!())? {
+                    model.wait();
+                    if once {
+                        break;
+                    }
+                } else if once {
+                    println!("{} is offline", model.composite_key());
+                    break;
+                }
+                // checked again once the model is due
+                thread::sleep(time::Duration::from_secs(1));
+            }
+            Ok(())
+        }
+    }
+}
+/// the model from the config with its options, or a new one with defaults
+fn config_model(config: &config::Config, platform: Platform, username: &str) -> Model {
+    match config.model(&platform, username) {
+        Some(model) => model.clone(),
+        None => Model::new(platform, username, ModelOptions::default()),
+    }
+}
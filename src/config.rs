@@ -1,21 +1,250 @@
 use {
     crate::{
-        e, h,
-        platforms::{Model, Platform},
-        s,
+        e, error, h,
+        hls::{self, Prefer, VariantPreference},
+        http::{self, HttpSettings},
+        info,
+        log::{self, LogSettings},
+        metrics,
+        muxer::{MuxOptions, OutputFormat},
+        platforms::{Model, ModelEntry, ModelOptions, Platform, RoomState},
+        s, status, stream, util, warn,
     },
     std::{
-        collections::{HashMap, HashSet},
+        collections::{BTreeSet, HashMap, HashSet},
         path::{Path, PathBuf},
         sync::Arc,
         *,
     },
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
+type Res<T> = Result<T, crate::err::Error>;
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct Settings {
     #[serde(rename = "user-agent", default = "default_useragent")]
     pub user_agent: String,
+    /// maximum bytes of downloaded segments held in memory across all recordings
+    #[serde(
+        rename = "memory-budget",
+        default = "default_memory_budget",
+        with = "util::serde_size"
+    )]
+    pub memory_budget: u64,
+    /// maximum bytes of downloaded segments held in memory per recording
+    #[serde(
+        rename = "recording-buffer",
+        default = "default_recording_buffer",
+        with = "util::serde_size"
+    )]
+    pub recording_buffer: u64,
+    /// logging level, format and file
+    #[serde(default, skip_serializing_if = "LogSettings::is_default")]
+    pub log: LogSettings,
+    /// timeouts, connection reuse and address lookup of requests
+    #[serde(default, skip_serializing_if = "HttpSettings::is_default")]
+    pub http: HttpSettings,
+    /// address of the http api ex. "0.0.0.0:8080", disabled if unset, read at startup
+    #[serde(rename = "api-address", skip_serializing_if = "Option::is_none")]
+    pub api_address: Option<String>,
+    /// token required by api requests that change models, sent as "Authorization: Bearer <token>", read at startup
+    #[serde(rename = "api-token", skip_serializing_if = "Option::is_none")]
+    pub api_token: Option<String>,
+    /// global defaults for settings that can be set per platform
+    #[serde(flatten)]
+    pub defaults: PlatformSettings,
+    /// ffmpeg arguments run on recordings after muxing by name
+    /// ex. {"h265": ["-c:v", "libx265", "-crf", "28", "-c:a", "copy"]}
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Vec<String>>,
+    /// per platform overrides of the global defaults
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub platform: HashMap<Platform, PlatformSettings>,
+}
+/// settings that can be set globally and overridden per platform
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlatformSettings {
+    /// maximum resolution to record ex. "1080p"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_resolution: Option<String>,
+    /// maximum variant bandwidth in bits per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bandwidth: Option<u64>,
+    /// which variant to favour under the limits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefer: Option<Prefer>,
+    /// time between checks of a model, default 1m
+    #[serde(
+        default,
+        with = "util::serde_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub poll_interval: Option<time::Duration>,
+    /// fraction of the interval checks are randomly moved by, default 0.1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll_jitter: Option<f64>,
+    /// length recordings are split into parts at
+    #[serde(
+        default,
+        with = "util::serde_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub split_duration: Option<time::Duration>,
+    /// size recordings are split into parts at
+    #[serde(
+        default,
+        with = "util::serde_option_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub split_size: Option<u64>,
+    /// container recordings are muxed to, default mkv
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_format: Option<OutputFormat>,
+    /// name of the ffmpeg profile run on recordings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// folder recordings are saved under, the model's output overrides it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_root: Option<PathBuf>,
+    /// path of recordings ex. "{root}/{platform}/{username}/{start:%Y-%m-%d}/{username}_{part}.{ext}"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_template: Option<String>,
+    /// recordings shorter than this are deleted instead of saved
+    #[serde(
+        default,
+        with = "util::serde_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_duration: Option<time::Duration>,
+    /// recordings smaller than this are deleted instead of saved
+    #[serde(
+        default,
+        with = "util::serde_option_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_size: Option<u64>,
+    /// http or socks proxy ex. "socks5h://127.0.0.1:9050", "" connects directly
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// requests started per second, unlimited if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requests_per_second: Option<f64>,
+    /// requests waiting for a response at once, unlimited if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrent: Option<usize>,
+    /// affiliate id used to list online rooms, models are checked one by one if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub affiliate_id: Option<String>,
+}
+impl PlatformSettings {
+    /// fills unset fields from other
+    fn or(&self, other: &Self) -> Self {
+        Self {
+            max_resolution: self
+                .max_resolution
+                .clone()
+                .or_else(|| other.max_resolution.clone()),
+            max_bandwidth: self.max_bandwidth.or(other.max_bandwidth),
+            prefer: self.prefer.or(other.prefer),
+            poll_interval: self.poll_interval.or(other.poll_interval),
+            poll_jitter: self.poll_jitter.or(other.poll_jitter),
+            split_duration: self.split_duration.or(other.split_duration),
+            split_size: self.split_size.or(other.split_size),
+            output_format: self.output_format.or(other.output_format),
+            profile: self.profile.clone().or_else(|| other.profile.clone()),
+            output_root: self
+                .output_root
+                .clone()
+                .or_else(|| other.output_root.clone()),
+            output_template: self
+                .output_template
+                .clone()
+                .or_else(|| other.output_template.clone()),
+            min_duration: self.min_duration.or(other.min_duration),
+            min_size: self.min_size.or(other.min_size),
+            proxy: self.proxy.clone().or_else(|| other.proxy.clone()),
+            requests_per_second: self.requests_per_second.or(other.requests_per_second),
+            max_concurrent: self.max_concurrent.or(other.max_concurrent),
+            affiliate_id: self
+                .affiliate_id
+                .clone()
+                .or_else(|| other.affiliate_id.clone()),
+        }
+    }
+}
+impl Settings {
+    /// settings for a platform, with unset fields taken from the global defaults
+    pub fn for_platform(&self, platform: &Platform) -> PlatformSettings {
+        match self.platform.get(platform) {
+            Some(platform_settings) => platform_settings.or(&self.defaults),
+            None => self.defaults.clone(),
+        }
+    }
+    /// variant limits for a model, the model's quality overrides the platform's max resolution
+    pub fn variant_preference(
+        &self,
+        platform: &Platform,
+        options: &ModelOptions,
+    ) -> VariantPreference {
+        let platform_settings = self.for_platform(platform);
+        let max_height = options
+            .quality
+            .as_deref()
+            .or(platform_settings.max_resolution.as_deref())
+            .and_then(hls::parse_height);
+        VariantPreference {
+            max_height,
+            max_bandwidth: platform_settings.max_bandwidth,
+            prefer: platform_settings.prefer.unwrap_or_default(),
+        }
+    }
+    /// time between checks of a model, the model's poll interval overrides the platform's
+    pub fn poll_interval(&self, platform: &Platform, options: &ModelOptions) -> time::Duration {
+        options
+            .poll_interval
+            .or(self.for_platform(platform).poll_interval)
+            .unwrap_or(time::Duration::from_secs(60))
+    }
+    /// length and size recordings are split at, the model's override the platform's
+    pub fn split(
+        &self,
+        platform: &Platform,
+        options: &ModelOptions,
+    ) -> (Option<time::Duration>, Option<u64>) {
+        let platform_settings = self.for_platform(platform);
+        (
+            options.split_duration.or(platform_settings.split_duration),
+            options.split_size.or(platform_settings.split_size),
+        )
+    }
+    /// output format and profile arguments of a model, the model's override the platform's
+    pub fn mux_options(&self, platform: &Platform, options: &ModelOptions) -> MuxOptions {
+        let platform_settings = self.for_platform(platform);
+        let profile = options
+            .profile
+            .as_ref()
+            .or(platform_settings.profile.as_ref());
+        let profile = match profile.map(|name| (name, self.profiles.get(name))) {
+            Some((_, Some(profile))) => profile.clone(),
+            Some((name, None)) => {
+                warn!("unknown profile {}", name);
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        MuxOptions {
+            format: options
+                .output_format
+                .or(platform_settings.output_format)
+                .unwrap_or_default(),
+            profile,
+        }
+    }
+    pub fn poll_jitter(&self, platform: &Platform) -> f64 {
+        self.for_platform(platform)
+            .poll_jitter
+            .unwrap_or(0.1)
+            .clamp(0.0, 1.0)
+    }
 }
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Config {
@@ -30,8 +259,8 @@ pub fn init(filepath: &Path) -> Res<Config> {
         Ok(r) => r,
         Err(e) => {
             if e.kind() == io::ErrorKind::NotFound {
-                println!(
-                    "configuration file created at {}\nplease fill with relevant data",
+                info!(
+                    "configuration file created at {}, please fill with relevant data",
                     filepath.display()
                 );
                 let config = Config::default(filepath);
@@ -43,16 +272,37 @@ pub fn init(filepath: &Path) -> Res<Config> {
     };
     let mut config: Config = serde_json::from_slice(&config_json_data).map_err(e!())?;
     config.json_location = PathBuf::from(filepath);
+    http::configure(&config.config).map_err(s!())?;
+    metrics::set_output_roots(config.output_roots()).map_err(s!())?;
     Ok(config)
 }
 impl Config {
+    /// folders recordings are saved under, "." for the working directory
+    fn output_roots(&self) -> BTreeSet<PathBuf> {
+        let platforms = self
+            .platform
+            .iter()
+            .filter(|(_, models)| !models.is_empty())
+            .map(|(platform, _)| {
+                self.config
+                    .for_platform(platform)
+                    .output_root
+                    .unwrap_or_else(|| PathBuf::from("."))
+            });
+        let models = self
+            .platform
+            .values()
+            .flatten()
+            .filter_map(|model| model.options.output.clone());
+        platforms.chain(models).collect()
+    }
     fn read(filepath: &Path) -> Res<Self> {
         let config_json_data = fs::read(filepath).map_err(e!())?;
         let mut config: Config = serde_json::from_slice(&config_json_data).map_err(e!())?;
         config.json_location = PathBuf::from(filepath);
         Ok(config)
     }
-    fn write(&self) -> Res<()> {
+    pub fn write(&self) -> Res<()> {
         let json = serde_json::to_string_pretty(&self).map_err(e!())?;
         fs::write(&self.json_location, json).map_err(e!())?;
         Ok(())
@@ -60,7 +310,7 @@ impl Config {
     /// adds a model
     fn model_add_unchecked_start(&mut self, mut model: Model) -> Res<()> {
         model
-            .download(Arc::new(self.config.clone()))
+            .download(Arc::new(self.config.clone()), None)
             .map_err(s!())?;
         if let Some(platform) = self.platform.get_mut(&model.platform) {
             platform.insert(model);
@@ -77,28 +327,87 @@ impl Config {
         if let Some(platform) = self.platform.get_mut(&model_ref.platform) {
             if let Some(model) = platform.take(model_ref) {
                 model.abort().map_err(s!())?;
+                status::untrack(&model.composite_key()).map_err(s!())?;
                 thread::spawn(move || drop(model));
             }
         }
         Ok(())
     }
+    /// updates the options of a tracked model
+    fn model_update_options(&mut self, model_ref: &Model) -> Res<()> {
+        if let Some(platform) = self.platform.get_mut(&model_ref.platform)
+            && let Some(mut model) = platform.take(model_ref)
+        {
+            model.set_options(model_ref.options.clone()).map_err(s!())?;
+            platform.insert(model);
+        }
+        Ok(())
+    }
+    /// settings shared with recordings
+    pub fn settings(&self) -> Arc<Settings> {
+        Arc::new(self.config.clone())
+    }
+    /// tracked models sorted by platform and username
+    pub fn models(&self) -> Vec<&Model> {
+        let mut models: Vec<&Model> = self.platform.values().flatten().collect();
+        models.sort_by_key(|m| m.composite_key());
+        models
+    }
+    /// tracked model by platform and username
+    pub fn model(&self, platform: &Platform, username: &str) -> Option<&Model> {
+        let key = Model::new(platform.clone(), username, ModelOptions::default());
+        self.platform.get(platform)?.get(&key)
+    }
+    /// adds a model without starting it, returns false if it is already tracked
+    pub fn model_add(&mut self, platform: Platform, username: &str) -> bool {
+        let model = Model::new(platform.clone(), username, ModelOptions::default());
+        self.platform.entry(platform).or_default().insert(model)
+    }
+    /// removes a model without stopping it, returns false if it was not tracked
+    pub fn model_remove(&mut self, platform: &Platform, username: &str) -> bool {
+        let key = Model::new(platform.clone(), username, ModelOptions::default());
+        self.platform
+            .get_mut(platform)
+            .is_some_and(|models| models.remove(&key))
+    }
     /// checks each model, and starts download if online
     pub fn download(&mut self) -> Res<()> {
         let mut headers: Vec<_> = Vec::new();
         let settings = Arc::new(self.config.clone());
-        for (_, mut models) in self.platform.drain() {
+        stream::memory_budget()
+            .set_limit(settings.memory_budget)
+            .map_err(s!())?;
+        for (platform, mut models) in self.platform.drain() {
             let s = settings.clone();
-            let handle: thread::JoinHandle<Result<HashSet<Model>, String>> =
-                thread::spawn(move || {
-                    for model_ref in models.clone() {
-                        if let Some(mut model) = models.take(&model_ref) {
-                            model.download(s.clone()).map_err(s!())?;
-                            models.insert(model);
-                            thread::sleep(time::Duration::from_millis(500));
+            let handle: thread::JoinHandle<Res<HashSet<Model>>> = thread::spawn(move || {
+                // one listing instead of a request per model where the platform supports it
+                let due = models.iter().any(|m| m.options.enabled && m.is_due());
+                let rooms = match due.then(|| platform.rooms(&s)).flatten() {
+                    Some(Ok(rooms)) => Some(rooms),
+                    Some(Err(e)) => {
+                        warn!(
+                            "{:?} listing failed, polling models one by one: {}",
+                            platform, e
+                        );
+                        None
+                    }
+                    None => None,
+                };
+                for model_ref in models.clone() {
+                    if let Some(mut model) = models.take(&model_ref) {
+                        let listed = rooms.as_ref().map(|rooms| {
+                            let username = model.username.to_lowercase();
+                            rooms.get(&username).copied().unwrap_or(RoomState::Offline)
+                        });
+                        let polled = model.download(s.clone(), listed).map_err(s!())?;
+                        models.insert(model);
+                        if polled {
+                            thread::sleep(platform.poll_delay());
                         }
                     }
-                    Ok(models)
-                });
+                }
+                Ok(models)
+            });
             headers.push(handle);
         }
         for header in headers {
@@ -114,7 +423,7 @@ impl Config {
         let new_config = match Self::read(&self.json_location).map_err(s!()) {
             Ok(r) => r,
             Err(e) => {
-                eprintln!("{}", e);
+                error!("{}", e);
                 return Ok(());
             }
         };
@@ -143,6 +452,18 @@ impl Config {
                     .map_err(s!())?
             }
         }
+        // update options
+        for key in new_set.intersection(&current_set) {
+            if let (Some(new_model), Some(current_model)) =
+                (new_map.get(*key), current_map.get(*key))
+                && new_model.options != current_model.options
+            {
+                self.model_update_options(new_model).map_err(s!())?
+            }
+        }
+        log::configure(&new_config.config.log);
+        http::configure(&new_config.config).map_err(s!())?;
+        metrics::set_output_roots(new_config.output_roots()).map_err(s!())?;
         self.config = new_config.config;
         Ok(())
     }
@@ -155,6 +476,15 @@ impl Config {
             json_location: json_location.to_path_buf(),
             config: Settings {
                 user_agent: String::new(),
+                memory_budget: default_memory_budget(),
+                recording_buffer: default_recording_buffer(),
+                api_address: None,
+                api_token: None,
+                log: LogSettings::default(),
+                http: HttpSettings::default(),
+                profiles: HashMap::new(),
+                defaults: PlatformSettings::default(),
+                platform: HashMap::new(),
             },
             platform,
         }
@@ -163,6 +493,12 @@ impl Config {
 fn default_useragent() -> String {
     "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/146.0.0.0 Safari/537.36 Edg/146.0.0.0".into()
 }
+fn default_memory_budget() -> u64 {
+    512 << 20
+}
+fn default_recording_buffer() -> u64 {
+    64 << 20
+}
 fn deserialize_platform<'de, D>(
     deserializer: D,
 ) -> Result<HashMap<Platform, HashSet<Model>>, D::Error>
@@ -170,14 +506,14 @@ where
     D: serde::Deserializer<'de>,
 {
     use serde::Deserialize;
-    let raw = HashMap::<String, Vec<String>>::deserialize(deserializer)?;
+    let raw = HashMap::<String, Vec<ModelEntry>>::deserialize(deserializer)?;
     let mut data: HashMap<Platform, HashSet<Model>> = HashMap::new();
-    for (platform_str, model_strs) in raw {
+    for (platform_str, model_entries) in raw {
         let platform_opt = Platform::new(&platform_str);
         if let Some(platform) = platform_opt {
-            let models: HashSet<Model> = model_strs
-                .iter()
-                .map(|model| Model::new(platform.clone(), model))
+            let models: HashSet<Model> = model_entries
+                .into_iter()
+                .map(|entry| entry.into_model(platform.clone()))
                 .collect();
             data.insert(platform, models);
         }
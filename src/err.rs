@@ -5,13 +5,7 @@ macro_rules! s {
     () => {
         |e| {
             let caller = panic::Location::caller();
-            format!(
-                "{}:({}:{}):{}",
-                caller.file(),
-                caller.line(),
-                caller.column(),
-                e
-            )
+            $crate::err::Error::from(e).at(caller)
         }
     };
 }
@@ -21,13 +15,7 @@ macro_rules! e {
     () => {
         |e| {
             let caller = panic::Location::caller();
-            format!(
-                "{}:({}:{}):{:?}",
-                caller.file(),
-                caller.line(),
-                caller.column(),
-                e
-            )
+            $crate::err::Error::foreign(e).at(caller)
         }
     };
 }
@@ -37,7 +25,7 @@ macro_rules! o {
     () => {
         || {
             let caller = panic::Location::caller();
-            format!("{}:({}:{})", caller.file(), caller.line(), caller.column())
+            $crate::err::Error::Missing.at(caller)
         }
     };
 }
@@ -47,13 +35,7 @@ macro_rules! h {
     () => {
         |e| {
             let caller = panic::Location::caller();
-            format!(
-                "{}:({}:{}):{}",
-                caller.file(),
-                caller.line(),
-                caller.column(),
-                $crate::err::header_cast(e)
-            )
+            $crate::err::Error::Other($crate::err::header_cast(e)).at(caller)
         }
     };
 }
@@ -66,11 +48,161 @@ pub fn header_cast(e: Box<dyn Any + Send>) -> String {
         "unknown panic".into()
     }
 }
-#[macro_export]
-macro_rules! debug_eprintln {
-    ($($arg:tt)*) => {
-        if env::var("DEBUG").is_ok() {
-            eprintln!($($arg)*);
+/// error with the locations it was mapped at, the root says what went wrong
+#[derive(Debug)]
+pub enum Error {
+    /// request failed before a response
+    Network(reqwest::Error),
+    /// response with an unsuccessful status
+    Http {
+        status: u16,
+        body: String,
+    },
+    /// unexpected data from a platform or file
+    Parse(String),
+    /// the model is not streaming
+    Offline,
+    /// the model does not exist on the platform
+    NotFound(String),
+    /// the stream is not available in this region
+    GeoBlocked,
+    /// requests to the platform or host are paused after repeated failures
+    Paused(String),
+    /// no space left for the recording
+    DiskFull,
+    /// ffmpeg could not mux the recording
+    MuxFailed(String),
+    Io(io::Error),
+    /// expected value was missing
+    Missing,
+    Other(String),
+    /// location an error passed through
+    Context {
+        location: String,
+        source: Box<Error>,
+    },
+}
+impl Error {
+    /// adds the location the error was mapped at
+    pub fn at(self, caller: &panic::Location) -> Self {
+        Error::Context {
+            location: format!("{}:({}:{})", caller.file(), caller.line(), caller.column()),
+            source: Box::new(self),
         }
-    };
+    }
+    /// the error without its locations
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+    /// http status of the response that caused the error
+    pub fn status(&self) -> Option<u16> {
+        match self.root() {
+            Error::Http { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+    /// converts errors of other crates, keeping the ones that can be reacted to
+    pub fn foreign<E: fmt::Debug + 'static>(e: E) -> Self {
+        let message = format!("{:?}", e);
+        let any: Box<dyn Any> = Box::new(e);
+        let any = match any.downcast::<Error>() {
+            Ok(e) => return *e,
+            Err(any) => any,
+        };
+        let any = match any.downcast::<reqwest::Error>() {
+            Ok(e) => return Error::Network(*e),
+            Err(any) => any,
+        };
+        let any = match any.downcast::<io::Error>() {
+            Ok(e) => return Error::from(*e),
+            Err(any) => any,
+        };
+        if any.is::<serde_json::Error>()
+            || any.is::<num::ParseIntError>()
+            || any.is::<num::ParseFloatError>()
+            || any.is::<base64::DecodeError>()
+        {
+            return Error::Parse(message);
+        }
+        Error::Other(message)
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Network(e) => {
+                write!(f, "{}", e)?;
+                let mut source = error::Error::source(e);
+                while let Some(e) = source {
+                    write!(f, ": {}", e)?;
+                    source = e.source();
+                }
+                Ok(())
+            }
+            Error::Http { status, body } => {
+                let reason = reqwest::StatusCode::from_u16(*status)
+                    .ok()
+                    .and_then(|s| s.canonical_reason())
+                    .unwrap_or_default();
+                write!(f, "{} {}-{}", status, reason, body)
+            }
+            Error::Parse(message) => write!(f, "parse error: {}", message),
+            Error::Offline => write!(f, "offline"),
+            Error::NotFound(name) => write!(f, "{} not found", name),
+            Error::GeoBlocked => write!(f, "geo-blocked"),
+            Error::Paused(key) => write!(f, "requests to {} are paused", key),
+            Error::DiskFull => write!(f, "disk full"),
+            Error::MuxFailed(output) => write!(f, "mux failed: {}", output),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Missing => write!(f, "missing value"),
+            Error::Other(message) => write!(f, "{}", message),
+            Error::Context { location, source } => write!(f, "{}:{}", location, source),
+        }
+    }
+}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Network(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::StorageFull => Error::DiskFull,
+            _ => Error::Io(e),
+        }
+    }
+}
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Network(e)
+    }
+}
+impl From<String> for Error {
+    fn from(e: String) -> Self {
+        Error::Other(e)
+    }
+}
+impl From<&str> for Error {
+    fn from(e: &str) -> Self {
+        Error::Other(e.to_string())
+    }
+}
+impl From<fmt::Error> for Error {
+    fn from(e: fmt::Error) -> Self {
+        Error::Other(e.to_string())
+    }
+}
+impl<T> From<sync::PoisonError<T>> for Error {
+    fn from(e: sync::PoisonError<T>) -> Self {
+        Error::Other(e.to_string())
+    }
 }
@@ -0,0 +1,72 @@
+use {
+    crate::{error, s},
+    std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::Mutex,
+        *,
+    },
+};
+type Res<T> = Result<T, crate::err::Error>;
+/// go-live times kept per model
+const KEPT: usize = 30;
+/// how close to a past go-live time of day counts as a usual start time
+const WINDOW: i64 = 30 * 60;
+const DAY: i64 = 24 * 60 * 60;
+/// times models went live, saved next to the config to poll more often around them
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+struct History {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// unix times by model key
+    starts: HashMap<String, Vec<i64>>,
+}
+static HISTORY: Mutex<Option<History>> = Mutex::new(None);
+fn with<T>(f: impl FnOnce(&mut History) -> T) -> Res<T> {
+    let mut history = HISTORY.lock().map_err(s!())?;
+    Ok(f(history.get_or_insert_with(History::default)))
+}
+/// "<config>.history.json" next to the config file
+pub fn path(config_path: &Path) -> PathBuf {
+    config_path.with_extension("history.json")
+}
+/// reads the history file, it is created on the first go-live
+pub fn load(path: &Path) -> Res<()> {
+    let mut history = match fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            error!("cannot read {}: {}", path.display(), e);
+            History::default()
+        }),
+        Err(_) => History::default(),
+    };
+    history.path = Some(path.to_path_buf());
+    with(|h| *h = history)
+}
+/// records that a model went live now
+pub fn went_live(key: &str) -> Res<()> {
+    let json = with(|h| {
+        let starts = h.starts.entry(key.to_string()).or_default();
+        starts.push(chrono::Utc::now().timestamp());
+        if starts.len() > KEPT {
+            starts.remove(0);
+        }
+        Some((h.path.clone()?, serde_json::to_string(h).ok()?))
+    })?;
+    if let Some((path, json)) = json {
+        fs::write(&path, json).map_err(s!())?;
+    }
+    Ok(())
+}
+/// whether the model usually goes live around this time of day
+pub fn is_usual_time(key: &str) -> bool {
+    let now = chrono::Utc::now().timestamp().rem_euclid(DAY);
+    with(|h| {
+        h.starts.get(key).is_some_and(|starts| {
+            starts.iter().any(|start| {
+                let difference = (start.rem_euclid(DAY) - now).abs();
+                difference.min(DAY - difference) <= WINDOW
+            })
+        })
+    })
+    .unwrap_or_default()
+}
@@ -0,0 +1,660 @@
+use std::{collections::HashMap, *};
+
+/// parses an attribute list ex. `BANDWIDTH=1280000,CODECS="avc1.4d401f,mp4a.40.2"`
+pub fn parse_attributes(attributes: &str) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    let mut rest = attributes.trim();
+    while !rest.is_empty() {
+        let Some(eq) = rest.find('=') else {
+            break;
+        };
+        let key = rest[..eq].trim().to_string();
+        rest = &rest[eq + 1..];
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let value = &quoted[..end];
+            rest = quoted.get(end + 1..).unwrap_or_default();
+            value
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value
+        };
+        map.insert(key, value.to_string());
+        rest = rest.trim_start_matches(',').trim_start();
+    }
+    map
+}
+/// parses a height from "720p", "1280x720" or "720"
+pub fn parse_height(text: &str) -> Option<u32> {
+    let text = text.trim().to_lowercase();
+    let height = match text.split_once('x') {
+        Some((_, height)) => height,
+        None => text.trim_end_matches('p'),
+    };
+    height.trim().parse::<u32>().ok()
+}
+/// one EXT-X-STREAM-INF entry of a master playlist
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub uri: String,
+    pub bandwidth: Option<u64>,
+    pub resolution: Option<(u32, u32)>,
+    pub frame_rate: Option<f64>,
+    /// GROUP-ID of the audio rendition
+    pub audio: Option<String>,
+}
+impl Variant {
+    pub fn height(&self) -> Option<u32> {
+        self.resolution.map(|(_, h)| h)
+    }
+}
+/// one EXT-X-MEDIA entry of a master playlist
+#[derive(Debug, Clone)]
+pub struct Rendition {
+    pub media_type: String,
+    pub group_id: String,
+    pub uri: Option<String>,
+    pub default: bool,
+}
+#[derive(Debug, Clone, Default)]
+pub struct MasterPlaylist {
+    pub variants: Vec<Variant>,
+    pub renditions: Vec<Rendition>,
+}
+/// which variant to favour among the ones under the caps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Prefer {
+    /// highest resolution, then bandwidth, then frame rate
+    #[default]
+    Resolution,
+    /// highest bandwidth
+    Bandwidth,
+    /// highest frame rate, then resolution
+    Framerate,
+    /// smallest variant
+    Lowest,
+}
+/// limits used to pick a variant
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VariantPreference {
+    pub max_height: Option<u32>,
+    pub max_bandwidth: Option<u64>,
+    pub prefer: Prefer,
+}
+impl MasterPlaylist {
+    pub fn parse(text: &str) -> Self {
+        let mut master = Self::default();
+        let mut pending: Option<HashMap<String, String>> = None;
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+                pending = Some(parse_attributes(attributes));
+                continue;
+            }
+            if let Some(attributes) = line.strip_prefix("#EXT-X-MEDIA:") {
+                let mut attributes = parse_attributes(attributes);
+                master.renditions.push(Rendition {
+                    media_type: attributes.remove("TYPE").unwrap_or_default(),
+                    group_id: attributes.remove("GROUP-ID").unwrap_or_default(),
+                    uri: attributes.remove("URI"),
+                    default: attributes.get("DEFAULT").is_some_and(|d| d == "YES"),
+                });
+                continue;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut attributes = pending.take().unwrap_or_default();
+            master.variants.push(Variant {
+                uri: line.to_string(),
+                bandwidth: attributes
+                    .get("AVERAGE-BANDWIDTH")
+                    .or_else(|| attributes.get("BANDWIDTH"))
+                    .and_then(|b| b.parse().ok()),
+                resolution: attributes
+                    .get("RESOLUTION")
+                    .and_then(|r| r.split_once('x'))
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?))),
+                frame_rate: attributes.get("FRAME-RATE").and_then(|f| f.parse().ok()),
+                audio: attributes.remove("AUDIO"),
+            });
+        }
+        master
+    }
+    /// picks the best variant at or under the caps, falls back to the smallest variant if none fit
+    pub fn select(&self, preference: &VariantPreference) -> Option<&Variant> {
+        let fits = |v: &&Variant| {
+            preference
+                .max_height
+                .is_none_or(|max| v.height().is_none_or(|h| h <= max))
+                && preference
+                    .max_bandwidth
+                    .is_none_or(|max| v.bandwidth.is_none_or(|b| b <= max))
+        };
+        let size = |v: &Variant| (v.height().unwrap_or(0), v.bandwidth.unwrap_or(0));
+        let rank = |v: &&Variant| -> (u64, u64, u64) {
+            let (height, bandwidth) = size(v);
+            let frame_rate = (v.frame_rate.unwrap_or(0.0) * 1000.0) as u64;
+            match preference.prefer {
+                Prefer::Resolution => (height as u64, bandwidth, frame_rate),
+                Prefer::Bandwidth => (bandwidth, height as u64, frame_rate),
+                Prefer::Framerate => (frame_rate, height as u64, bandwidth),
+                Prefer::Lowest => (u64::MAX - height as u64, u64::MAX - bandwidth, 0),
+            }
+        };
+        self.variants
+            .iter()
+            .filter(fits)
+            .max_by_key(rank)
+            .or_else(|| self.variants.iter().min_by_key(|v| size(v)))
+    }
+    /// audio rendition used by a variant, for audio/video split streams
+    pub fn audio_for(&self, variant: &Variant) -> Option<&Rendition> {
+        let audio = self
+            .renditions
+            .iter()
+            .filter(|r| r.media_type == "AUDIO" && r.uri.is_some());
+        match variant.audio.as_ref() {
+            Some(group) => audio
+                .clone()
+                .filter(|r| &r.group_id == group)
+                .max_by_key(|r| r.default)
+                .or_else(|| audio.clone().next()),
+            None => audio.clone().next(),
+        }
+    }
+}
+/// resolves a uri from a playlist against the playlist's url
+pub fn resolve(base: &str, uri: &str) -> String {
+    if uri.contains("://") {
+        return uri.to_string();
+    }
+    let scheme_end = base.find("://").map(|n| n + 3).unwrap_or(0);
+    if let Some(rest) = uri.strip_prefix("//") {
+        return format!("{}{}", &base[..scheme_end], rest);
+    }
+    let base = base.split(['?', '#']).next().unwrap_or(base);
+    if uri.starts_with('/') {
+        let host_end = base[scheme_end..]
+            .find('/')
+            .map(|n| n + scheme_end)
+            .unwrap_or(base.len());
+        return format!("{}{}", &base[..host_end], uri);
+    }
+    match base[scheme_end..].rfind('/') {
+        Some(n) => format!("{}{}", &base[..n + scheme_end + 1], uri),
+        None => format!("{}/{}", base, uri),
+    }
+}
+/// EXT-X-BYTERANGE or BYTERANGE attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub length: u64,
+    pub offset: u64,
+}
+impl ByteRange {
+    /// parses "<length>[@<offset>]", offset defaults to the end of the previous range
+    fn parse(text: &str, previous_end: u64) -> Option<Self> {
+        let (length, offset) = match text.trim().split_once('@') {
+            Some((length, offset)) => (length, Some(offset)),
+            None => (text.trim(), None),
+        };
+        Some(Self {
+            length: length.parse().ok()?,
+            offset: match offset {
+                Some(offset) => offset.parse().ok()?,
+                None => previous_end,
+            },
+        })
+    }
+    /// value of the http range header
+    pub fn header(&self) -> String {
+        format!(
+            "bytes={}-{}",
+            self.offset,
+            self.offset + self.length.saturating_sub(1)
+        )
+    }
+}
+/// EXT-X-MAP initialization section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Map {
+    pub uri: String,
+    pub byte_range: Option<ByteRange>,
+}
+/// EXT-X-KEY encryption method of the following segments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Key {
+    pub method: String,
+    pub uri: Option<String>,
+    pub iv: Option<String>,
+}
+/// EXT-X-PART of a low latency playlist, not recorded on its own yet
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Part {
+    pub uri: String,
+    pub duration: f64,
+    pub independent: bool,
+    pub byte_range: Option<ByteRange>,
+}
+/// one media segment of a media playlist
+#[derive(Debug, Clone)]
+pub struct MediaSegment {
+    /// absolute url
+    pub uri: String,
+    pub duration: f64,
+    /// EXT-X-MEDIA-SEQUENCE based sequence number, None if the playlist has no media sequence
+    pub sequence: Option<u64>,
+    /// EXT-X-PROGRAM-DATE-TIME, or implied from the previous segment
+    pub program_date_time: Option<chrono::DateTime<chrono::FixedOffset>>,
+    pub map: Option<Map>,
+    pub discontinuity: bool,
+    pub key: Option<Key>,
+    pub byte_range: Option<ByteRange>,
+    /// EXT-X-PART lines before the segment's uri
+    #[allow(dead_code)]
+    pub parts: Vec<Part>,
+    /// tags not handled by the parser since the previous segment
+    pub tags: Vec<String>,
+}
+#[derive(Debug, Clone, Default)]
+pub struct MediaPlaylist {
+    pub target_duration: Option<f64>,
+    /// None if the playlist has no EXT-X-MEDIA-SEQUENCE tag
+    pub media_sequence: Option<u64>,
+    pub discontinuity_sequence: u64,
+    pub end_list: bool,
+    pub segments: Vec<MediaSegment>,
+    /// parts after the last segment, of the segment still being produced
+    #[allow(dead_code)]
+    pub preload_parts: Vec<Part>,
+    /// all tags not handled by the parser
+    pub tags: Vec<String>,
+}
+fn parse_date(text: &str) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    let text = text.trim();
+    chrono::DateTime::parse_from_rfc3339(text)
+        .or_else(|_| chrono::DateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f%z"))
+        .ok()
+}
+impl MediaPlaylist {
+    /// parses a media playlist, uris are resolved against base_url
+    pub fn parse(text: &str, base_url: &str) -> Self {
+        let mut media = Self::default();
+        let mut duration = 0.0;
+        let mut date: Option<chrono::DateTime<chrono::FixedOffset>> = None;
+        let mut map: Option<Map> = None;
+        let mut key: Option<Key> = None;
+        let mut discontinuity = false;
+        let mut byte_range: Option<ByteRange> = None;
+        let mut range_end: u64 = 0;
+        let mut parts: Vec<Part> = Vec::new();
+        let mut part_end: u64 = 0;
+        let mut tags: Vec<String> = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if !line.starts_with('#') {
+                let sequence = media
+                    .media_sequence
+                    .map(|n| n + media.segments.len() as u64);
+                // implied date from the previous segment
+                let program_date_time = date.take().or_else(|| {
+                    let previous = media.segments.last()?;
+                    let millis = (previous.duration * 1000.0) as i64;
+                    Some(previous.program_date_time? + chrono::Duration::milliseconds(millis))
+                });
+                if let Some(range) = byte_range {
+                    range_end = range.offset + range.length;
+                }
+                media.segments.push(MediaSegment {
+                    uri: resolve(base_url, line),
+                    duration,
+                    sequence,
+                    program_date_time,
+                    map: map.clone(),
+                    discontinuity,
+                    key: key.clone(),
+                    byte_range: byte_range.take(),
+                    parts: mem::take(&mut parts),
+                    tags: mem::take(&mut tags),
+                });
+                duration = 0.0;
+                discontinuity = false;
+                continue;
+            }
+            let (tag, value) = line.split_once(':').unwrap_or((line, ""));
+            match tag {
+                "#EXTINF" => {
+                    duration = value
+                        .split(',')
+                        .next()
+                        .and_then(|d| d.trim().parse().ok())
+                        .unwrap_or(0.0);
+                }
+                "#EXT-X-TARGETDURATION" => media.target_duration = value.trim().parse().ok(),
+                "#EXT-X-MEDIA-SEQUENCE" => media.media_sequence = value.trim().parse().ok(),
+                "#EXT-X-DISCONTINUITY-SEQUENCE" => {
+                    media.discontinuity_sequence = value.trim().parse().unwrap_or(0)
+                }
+                "#EXT-X-ENDLIST" => media.end_list = true,
+                "#EXT-X-DISCONTINUITY" => discontinuity = true,
+                "#EXT-X-PROGRAM-DATE-TIME" => date = parse_date(value),
+                "#EXT-X-BYTERANGE" => byte_range = ByteRange::parse(value, range_end),
+                "#EXT-X-MAP" => {
+                    let attributes = parse_attributes(value);
+                    map = attributes.get("URI").map(|uri| Map {
+                        uri: resolve(base_url, uri),
+                        byte_range: attributes
+                            .get("BYTERANGE")
+                            .and_then(|r| ByteRange::parse(r, 0)),
+                    });
+                }
+                "#EXT-X-KEY" => {
+                    let mut attributes = parse_attributes(value);
+                    let method = attributes.remove("METHOD").unwrap_or_default();
+                    key = match method.as_str() {
+                        "NONE" | "" => None,
+                        _ => Some(Key {
+                            method,
+                            uri: attributes.remove("URI").map(|uri| resolve(base_url, &uri)),
+                            iv: attributes.remove("IV"),
+                        }),
+                    };
+                }
+                "#EXT-X-PART" => {
+                    let attributes = parse_attributes(value);
+                    if let Some(uri) = attributes.get("URI") {
+                        let byte_range = attributes
+                            .get("BYTERANGE")
+                            .and_then(|r| ByteRange::parse(r, part_end));
+                        if let Some(range) = byte_range {
+                            part_end = range.offset + range.length;
+                        }
+                        parts.push(Part {
+                            uri: resolve(base_url, uri),
+                            duration: attributes
+                                .get("DURATION")
+                                .and_then(|d| d.parse().ok())
+                                .unwrap_or(0.0),
+                            independent: attributes.get("INDEPENDENT").is_some_and(|i| i == "YES"),
+                            byte_range,
+                        });
+                    }
+                }
+                "#EXTM3U" | "#EXT-X-VERSION" => (),
+                _ => {
+                    tags.push(line.to_string());
+                    media.tags.push(line.to_string());
+                }
+            }
+        }
+        media.preload_parts = parts;
+        media
+    }
+    /// date of the first segment with a known EXT-X-PROGRAM-DATE-TIME
+    pub fn start_date(&self) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+        self.segments.iter().find_map(|s| s.program_date_time)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const MASTER: &str = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360,FRAME-RATE=30.000
+360.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=2500000,RESOLUTION=1280x720,FRAME-RATE=30.000
+720.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=3500000,RESOLUTION=1280x720,FRAME-RATE=60.000
+720p60.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080,FRAME-RATE=30.000
+1080.m3u8
+";
+    fn select(preference: VariantPreference) -> String {
+        let master = MasterPlaylist::parse(MASTER);
+        master.select(&preference).unwrap().uri.clone()
+    }
+    #[test]
+    fn select_highest_by_default() {
+        assert_eq!(select(VariantPreference::default()), "1080.m3u8");
+    }
+    #[test]
+    fn select_max_height() {
+        let preference = VariantPreference {
+            max_height: Some(720),
+            ..Default::default()
+        };
+        // both 720p variants fit, the higher bandwidth breaks the tie
+        assert_eq!(select(preference), "720p60.m3u8");
+        let preference = VariantPreference {
+            max_height: Some(240),
+            ..Default::default()
+        };
+        // nothing fits, smallest variant
+        assert_eq!(select(preference), "360.m3u8");
+    }
+    #[test]
+    fn select_max_bandwidth() {
+        let preference = VariantPreference {
+            max_bandwidth: Some(3_000_000),
+            ..Default::default()
+        };
+        assert_eq!(select(preference), "720.m3u8");
+    }
+    #[test]
+    fn select_prefer_ties() {
+        let preference = VariantPreference {
+            max_height: Some(720),
+            prefer: Prefer::Framerate,
+            ..Default::default()
+        };
+        assert_eq!(select(preference), "720p60.m3u8");
+        let preference = VariantPreference {
+            prefer: Prefer::Bandwidth,
+            ..Default::default()
+        };
+        assert_eq!(select(preference), "1080.m3u8");
+        let preference = VariantPreference {
+            prefer: Prefer::Lowest,
+            ..Default::default()
+        };
+        assert_eq!(select(preference), "360.m3u8");
+        // equal height and bandwidth, frame rate decides
+        let master = MasterPlaylist::parse(
+            "#EXT-X-STREAM-INF:BANDWIDTH=1000,RESOLUTION=1280x720,FRAME-RATE=30\na.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=1000,RESOLUTION=1280x720,FRAME-RATE=60\nb.m3u8\n",
+        );
+        let variant = master.select(&VariantPreference::default()).unwrap();
+        assert_eq!(variant.uri, "b.m3u8");
+    }
+    #[test]
+    fn media_sequence() {
+        let text = "#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:7\n#EXTINF:2.0,\na.ts\n#EXTINF:2.0,\nb.ts\n";
+        let media = MediaPlaylist::parse(text, "https://host/live/media.m3u8");
+        assert_eq!(media.media_sequence, Some(7));
+        let sequences: Vec<_> = media.segments.iter().map(|s| s.sequence).collect();
+        assert_eq!(sequences, [Some(7), Some(8)]);
+        let media = MediaPlaylist::parse("#EXTINF:2.0,\na.ts\n", "https://host/media.m3u8");
+        assert_eq!(media.media_sequence, None);
+        assert_eq!(media.segments[0].sequence, None);
+        assert_eq!(media.segments[0].duration, 2.0);
+    }
+    #[test]
+    fn byte_range() {
+        let text = "#EXTINF:1,\n#EXT-X-BYTERANGE:100@50\nall.ts\n\
+                    #EXTINF:1,\n#EXT-X-BYTERANGE:200\nall.ts\n\
+                    #EXTINF:1,\nother.ts\n";
+        let media = MediaPlaylist::parse(text, "https://host/media.m3u8");
+        let ranges: Vec<_> = media.segments.iter().map(|s| s.byte_range).collect();
+        assert_eq!(
+            ranges,
+            [
+                Some(ByteRange {
+                    length: 100,
+                    offset: 50
+                }),
+                // continues after the previous range
+                Some(ByteRange {
+                    length: 200,
+                    offset: 150
+                }),
+                None,
+            ]
+        );
+        assert_eq!(ranges[1].unwrap().header(), "bytes=150-349");
+    }
+    #[test]
+    fn map() {
+        let text = "#EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@0\"\n#EXTINF:2,\n0.m4s\n\
+                    #EXT-X-MAP:URI=\"init2.mp4\"\n#EXTINF:2,\n1.m4s\n";
+        let media = MediaPlaylist::parse(text, "https://host/live/media.m3u8?token=1");
+        assert_eq!(
+            media.segments[0].map,
+            Some(Map {
+                uri: "https://host/live/init.mp4".into(),
+                byte_range: Some(ByteRange {
+                    length: 720,
+                    offset: 0
+                }),
+            })
+        );
+        assert_eq!(
+            media.segments[1].map.as_ref().map(|m| m.uri.as_str()),
+            Some("https://host/live/init2.mp4")
+        );
+    }
+    #[test]
+    fn program_date_time() {
+        let text = "#EXT-X-PROGRAM-DATE-TIME:2024-02-29T23:12:00.000Z\n#EXTINF:2.5,\na.ts\n\
+                    #EXTINF:2,\nb.ts\n\
+                    #EXT-X-PROGRAM-DATE-TIME:2024-02-29T23:13:00.000+00:00\n#EXTINF:2,\nc.ts\n";
+        let media = MediaPlaylist::parse(text, "https://host/media.m3u8");
+        let date = |s: &str| chrono::DateTime::parse_from_rfc3339(s).unwrap();
+        let dates: Vec<_> = media.segments.iter().map(|s| s.program_date_time).collect();
+        assert_eq!(
+            dates,
+            [
+                Some(date("2024-02-29T23:12:00Z")),
+                // implied from the previous segment
+                Some(date("2024-02-29T23:12:02.5Z")),
+                Some(date("2024-02-29T23:13:00Z")),
+            ]
+        );
+        assert_eq!(media.start_date(), dates[0]);
+    }
+    #[test]
+    fn discontinuity() {
+        let text = "#EXT-X-DISCONTINUITY-SEQUENCE:3\n#EXTINF:2,\na.ts\n\
+                    #EXT-X-DISCONTINUITY\n#EXTINF:2,\nb.ts\n#EXTINF:2,\nc.ts\n#EXT-X-ENDLIST\n";
+        let media = MediaPlaylist::parse(text, "https://host/media.m3u8");
+        assert_eq!(media.discontinuity_sequence, 3);
+        let discontinuities: Vec<_> = media.segments.iter().map(|s| s.discontinuity).collect();
+        assert_eq!(discontinuities, [false, true, false]);
+        assert!(media.end_list);
+    }
+    #[test]
+    fn key_and_tags() {
+        let text = "#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x01\n#EXT-X-CUSTOM:1\n#EXTINF:2,\na.ts\n\
+                    #EXT-X-KEY:METHOD=NONE\n#EXTINF:2,\nb.ts\n";
+        let media = MediaPlaylist::parse(text, "https://host/live/media.m3u8");
+        assert_eq!(
+            media.segments[0].key,
+            Some(Key {
+                method: "AES-128".into(),
+                uri: Some("https://host/live/key.bin".into()),
+                iv: Some("0x01".into()),
+            })
+        );
+        assert_eq!(media.segments[1].key, None);
+        assert_eq!(media.segments[0].tags, ["#EXT-X-CUSTOM:1"]);
+        assert!(media.segments[1].tags.is_empty());
+    }
+    #[test]
+    fn relative_uris() {
+        let base = "https://host/live/stream/media.m3u8?token=abc";
+        assert_eq!(resolve(base, "a.ts"), "https://host/live/stream/a.ts");
+        assert_eq!(resolve(base, "../a.ts"), "https://host/live/stream/../a.ts");
+        assert_eq!(resolve(base, "/root/a.ts"), "https://host/root/a.ts");
+        assert_eq!(resolve(base, "//cdn/a.ts"), "https://cdn/a.ts");
+        assert_eq!(resolve(base, "http://other/a.ts"), "http://other/a.ts");
+        assert_eq!(resolve("https://host", "a.ts"), "https://host/a.ts");
+        let media = MediaPlaylist::parse("#EXTINF:2,\nsub/a.ts?x=1\n", base);
+        assert_eq!(
+            media.segments[0].uri,
+            "https://host/live/stream/sub/a.ts?x=1"
+        );
+    }
+    #[test]
+    fn master_audio() {
+        let master = MasterPlaylist::parse(
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"en\",DEFAULT=NO,URI=\"en.m3u8\"\n\
+             #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"de\",DEFAULT=YES,URI=\"de.m3u8\"\n\
+             #EXT-X-STREAM-INF:AVERAGE-BANDWIDTH=900,BANDWIDTH=1000,RESOLUTION=1280x720,AUDIO=\"aac\"\n\
+             video.m3u8\n",
+        );
+        let variant = &master.variants[0];
+        assert_eq!(variant.bandwidth, Some(900));
+        assert_eq!(variant.height(), Some(720));
+        let audio = master.audio_for(variant).unwrap();
+        assert_eq!(audio.uri.as_deref(), Some("de.m3u8"));
+    }
+    #[test]
+    fn heights() {
+        assert_eq!(parse_height("720p"), Some(720));
+        assert_eq!(parse_height("1280x720"), Some(720));
+        assert_eq!(parse_height("1080"), Some(1080));
+        assert_eq!(parse_height("best"), None);
+    }
+    #[test]
+    fn parts() {
+        let text = "#EXT-X-MEDIA-SEQUENCE:3\n\
+                    #EXT-X-PART:DURATION=0.5,URI=\"3.0.m4s\",INDEPENDENT=YES\n\
+                    #EXT-X-PART:DURATION=0.5,URI=\"3.1.m4s\"\n\
+                    #EXTINF:1.0,\n3.m4s\n\
+                    #EXT-X-PART:DURATION=0.5,URI=\"all.m4s\",BYTERANGE=\"100@0\"\n\
+                    #EXT-X-PART:DURATION=0.5,URI=\"all.m4s\",BYTERANGE=\"200\"\n\
+                    #EXTINF:1.0,\n4.m4s\n\
+                    #EXT-X-PART:DURATION=0.5,URI=\"5.0.m4s\",INDEPENDENT=YES\n\
+                    #EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"5.1.m4s\"\n";
+        let media = MediaPlaylist::parse(text, "https://host/live/media.m3u8");
+        assert_eq!(media.segments.len(), 2);
+        let first = &media.segments[0].parts;
+        let uris: Vec<_> = first.iter().map(|p| p.uri.as_str()).collect();
+        assert_eq!(
+            uris,
+            ["https://host/live/3.0.m4s", "https://host/live/3.1.m4s"]
+        );
+        assert_eq!(first[0].duration, 0.5);
+        assert!(first[0].independent && !first[1].independent);
+        assert_eq!(first[0].byte_range, None);
+        // byte ranges of parts continue after the previous part
+        let ranges: Vec<_> = media.segments[1]
+            .parts
+            .iter()
+            .map(|p| p.byte_range)
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                Some(ByteRange {
+                    length: 100,
+                    offset: 0
+                }),
+                Some(ByteRange {
+                    length: 200,
+                    offset: 100
+                }),
+            ]
+        );
+        // parts of the segment still being produced
+        assert_eq!(media.preload_parts.len(), 1);
+        assert_eq!(media.preload_parts[0].uri, "https://host/live/5.0.m4s");
+        assert!(media.tags.iter().all(|t| !t.starts_with("#EXT-X-PART:")));
+    }
+}
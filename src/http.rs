@@ -0,0 +1,296 @@
+use {
+    crate::{config::Settings, e, err::Error, info, platforms::Platform, s, util, warn},
+    reqwest::blocking::{Client, Response},
+    std::{
+        collections::HashMap,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+        sync::{Condvar, Mutex},
+        time::{Duration, Instant},
+        *,
+    },
+};
+type Res<T> = Result<T, crate::err::Error>;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// consecutive failed requests that pause a platform or host
+const FAILURES: u32 = 10;
+/// how long requests are paused after repeated failures
+const PAUSE: Duration = Duration::from_secs(120);
+/// longest retry-after waited for, longer ones pause requests instead
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// which addresses connections are made to
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+/// http client settings, set in "config.http"
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct HttpSettings {
+    /// time to establish a connection, default 10s
+    #[serde(with = "util::serde_duration", skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<Duration>,
+    /// time to wait for a response and for each read of its body, default 30s
+    #[serde(with = "util::serde_duration", skip_serializing_if = "Option::is_none")]
+    pub read_timeout: Option<Duration>,
+    /// time unused connections are kept open for reuse, default 90s
+    #[serde(with = "util::serde_duration", skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<Duration>,
+    /// addresses used for hosts instead of looking them up ex. {"example.com": "1.2.3.4"}
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub resolve: HashMap<String, IpAddr>,
+    #[serde(skip_serializing_if = "is_any")]
+    pub ip_version: IpVersion,
+}
+fn is_any(ip_version: &IpVersion) -> bool {
+    *ip_version == IpVersion::Any
+}
+impl HttpSettings {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+#[derive(Default)]
+struct Clients {
+    settings: HttpSettings,
+    /// proxy of each platform
+    proxies: HashMap<Platform, Option<String>>,
+    /// clients by proxy, built on first use and shared so connections are reused
+    clients: HashMap<Option<String>, Client>,
+}
+static CLIENTS: Mutex<Option<Clients>> = Mutex::new(None);
+/// applies http settings, proxies and request limits, clients are rebuilt when they change
+pub fn configure(settings: &Settings) -> Res<()> {
+    configure_limits(settings).map_err(s!())?;
+    let proxies: HashMap<Platform, Option<String>> = Platform::list()
+        .into_iter()
+        .map(|p| {
+            let proxy = settings.for_platform(&p).proxy;
+            (p, proxy)
+        })
+        .collect();
+    let mut clients = CLIENTS.lock().map_err(s!())?;
+    if let Some(c) = clients.as_ref()
+        && c.settings == settings.http
+        && c.proxies == proxies
+    {
+        return Ok(());
+    }
+    *clients = Some(Clients {
+        settings: settings.http.clone(),
+        proxies,
+        clients: HashMap::new(),
+    });
+    Ok(())
+}
+/// shared client for a request of the platform, with its proxy
+pub fn client(platform: &Platform) -> Res<Client> {
+    let mut clients = CLIENTS.lock().map_err(s!())?;
+    let clients = clients.get_or_insert_with(Clients::default);
+    let proxy = clients.proxies.get(platform).cloned().flatten();
+    if let Some(client) = clients.clients.get(&proxy) {
+        return Ok(client.clone());
+    }
+    let client = build(&clients.settings, proxy.as_deref()).map_err(s!())?;
+    clients.clients.insert(proxy, client.clone());
+    Ok(client)
+}
+/// an empty proxy connects directly, no proxy uses the proxy environment variables
+fn build(settings: &HttpSettings, proxy: Option<&str>) -> Res<Client> {
+    let mut builder = Client::builder()
+        .connect_timeout(settings.connect_timeout.unwrap_or(CONNECT_TIMEOUT))
+        .timeout(settings.read_timeout.unwrap_or(READ_TIMEOUT))
+        .pool_idle_timeout(settings.idle_timeout.unwrap_or(IDLE_TIMEOUT))
+        .tcp_keepalive(IDLE_TIMEOUT);
+    builder = match proxy {
+        Some("") => builder.no_proxy(),
+        Some(proxy) => builder.proxy(reqwest::Proxy::all(proxy).map_err(e!())?),
+        None => builder,
+    };
+    for (host, ip) in &settings.resolve {
+        // the port is taken from the url
+        builder = builder.resolve(host, SocketAddr::new(*ip, 0));
+    }
+    builder = match settings.ip_version {
+        IpVersion::Any => builder,
+        IpVersion::V4 => builder.local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        IpVersion::V6 => builder.local_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+    };
+    builder.build().map_err(e!())
+}
+#[derive(Clone, Copy, Default)]
+struct Limits {
+    requests_per_second: Option<f64>,
+    max_concurrent: Option<usize>,
+}
+impl Limits {
+    fn new(requests_per_second: Option<f64>, max_concurrent: Option<usize>) -> Self {
+        Self {
+            requests_per_second: requests_per_second.filter(|r| *r > 0.0),
+            max_concurrent: max_concurrent.filter(|m| *m > 0),
+        }
+    }
+}
+/// requests of a platform, or of a host for platforms limited per host
+#[derive(Default)]
+struct Limiter {
+    /// earliest time the next request can be sent
+    next: Option<Instant>,
+    /// requests waiting for a response
+    active: usize,
+    /// consecutive failed requests
+    failures: u32,
+    paused_until: Option<Instant>,
+}
+#[derive(Default)]
+struct Limiters {
+    platforms: HashMap<Platform, Limits>,
+    limiters: HashMap<String, Limiter>,
+}
+static LIMITERS: Mutex<Option<Limiters>> = Mutex::new(None);
+/// notified when a request finishes
+static FREED: Condvar = Condvar::new();
+fn configure_limits(settings: &Settings) -> Res<()> {
+    let mut limiters = LIMITERS.lock().map_err(s!())?;
+    let limiters = limiters.get_or_insert_with(Limiters::default);
+    limiters.platforms = Platform::list()
+        .into_iter()
+        .map(|p| {
+            let platform_settings = settings.for_platform(&p);
+            let limits = Limits::new(
+                platform_settings.requests_per_second,
+                platform_settings.max_concurrent,
+            );
+            (p, limits)
+        })
+        .collect();
+    FREED.notify_all();
+    Ok(())
+}
+/// a request slot, freed when dropped
+pub struct Permit {
+    key: String,
+}
+/// waits for the request limits of the platform, or of the url's host for platforms limited per host
+pub fn acquire(platform: &Platform, url: &str) -> Res<Permit> {
+    let key = match platform.limit_per_host() {
+        true => host(url).to_string(),
+        false => format!("{:?}", platform),
+    };
+    let mut guard = LIMITERS.lock().map_err(s!())?;
+    loop {
+        let limiters = guard.get_or_insert_with(Limiters::default);
+        let limits = limiters
+            .platforms
+            .get(platform)
+            .copied()
+            .unwrap_or_default();
+        let limiter = limiters.limiters.entry(key.clone()).or_default();
+        let now = Instant::now();
+        if limiter.paused_until.is_some_and(|until| now < until) {
+            return Err(Error::Paused(key)).map_err(s!());
+        }
+        if limits
+            .max_concurrent
+            .is_some_and(|max| limiter.active >= max)
+        {
+            guard = FREED.wait(guard).map_err(s!())?;
+            continue;
+        }
+        limiter.active += 1;
+        let start = limiter.next.map_or(now, |next| next.max(now));
+        let interval = limits
+            .requests_per_second
+            .map_or(Duration::ZERO, |r| Duration::from_secs_f64(1.0 / r));
+        limiter.next = Some(start + interval);
+        drop(guard);
+        thread::sleep(start - now);
+        return Ok(Permit { key });
+    }
+}
+impl Permit {
+    /// records the response, failures and long retry-afters pause further requests
+    pub fn finish(&self, response: Option<&Response>) {
+        let status = response.map(|r| r.status().as_u16());
+        let failed = status.is_none_or(|s| s == 429 || s >= 500);
+        let retry_after = response.and_then(retry_after);
+        let Ok(mut limiters) = LIMITERS.lock() else {
+            return;
+        };
+        let Some(limiter) = limiters
+            .as_mut()
+            .and_then(|l| l.limiters.get_mut(&self.key))
+        else {
+            return;
+        };
+        let now = Instant::now();
+        match retry_after {
+            Some(retry_after) if retry_after > MAX_RETRY_AFTER => {
+                warn!(
+                    "{} asked to wait {}, pausing requests",
+                    self.key,
+                    util::format_duration(retry_after)
+                );
+                limiter.paused_until = Some(now + retry_after);
+            }
+            Some(retry_after) => {
+                limiter.next = limiter.next.max(Some(now + retry_after));
+            }
+            None => {}
+        }
+        if !failed {
+            if limiter.failures >= FAILURES {
+                info!("requests to {} resumed", self.key);
+            }
+            limiter.failures = 0;
+            return;
+        }
+        limiter.failures += 1;
+        if limiter.failures >= FAILURES && limiter.paused_until.is_none_or(|until| until <= now) {
+            warn!(
+                "{} failed requests to {}, pausing for {}",
+                limiter.failures,
+                self.key,
+                util::format_duration(PAUSE)
+            );
+            limiter.paused_until = Some(now + PAUSE);
+        }
+    }
+}
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Ok(mut limiters) = LIMITERS.lock()
+            && let Some(limiter) = limiters
+                .as_mut()
+                .and_then(|l| l.limiters.get_mut(&self.key))
+        {
+            limiter.active = limiter.active.saturating_sub(1);
+        }
+        FREED.notify_all();
+    }
+}
+/// the retry-after header in seconds or as a date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+/// "example.com" of "https://example.com/path"
+fn host(url: &str) -> &str {
+    let url = url.split_once("://").map(|(_, u)| u).unwrap_or(url);
+    url.split(['/', '?', '#']).next().unwrap_or(url)
+}
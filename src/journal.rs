@@ -0,0 +1,281 @@
+use {
+    crate::{
+        e, error, info, log,
+        muxer::MuxOptions,
+        platforms::{Platform, StreamInfo},
+        s,
+        stream::{self, Gap},
+        util::{self, ManagedFile},
+    },
+    base64::{Engine, engine::general_purpose::STANDARD},
+    std::{
+        io::Seek,
+        path::{Path, PathBuf},
+        sync::Mutex,
+        *,
+    },
+};
+type Res<T> = Result<T, crate::err::Error>;
+/// on disk record of an in-progress recording, kept next to its temp files
+/// so the recording can be finished or continued after a crash
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Journal {
+    #[serde(skip)]
+    path: PathBuf,
+    pub platform: Platform,
+    pub username: String,
+    pub video: TempFile,
+    pub audio: Option<TempFile>,
+    /// position of the last segment written
+    pub last_segment: Option<u64>,
+    /// last_segment is a media sequence number
+    pub sequenced: bool,
+    pub last_date: Option<String>,
+    pub last_duration: f64,
+    /// EXT-X-PROGRAM-DATE-TIME of the first segment written
+    #[serde(default)]
+    pub first_date: Option<String>,
+    /// wall clock time the recording started
+    #[serde(default)]
+    pub started: Option<String>,
+    /// number of segments written
+    #[serde(default)]
+    pub segments: u64,
+    /// seconds of segments written
+    #[serde(default)]
+    pub duration: f64,
+    /// seconds of segments skipped between the ones written
+    #[serde(default)]
+    pub missing: f64,
+    /// base64 mp4 header of the video
+    pub mp4_header: Option<String>,
+    pub mp4_header_audio: Option<String>,
+    pub gaps: Vec<Gap>,
+    #[serde(default)]
+    pub info: StreamInfo,
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+    /// output format and profile of the recording
+    #[serde(default)]
+    pub mux: MuxOptions,
+    /// part of a recording split by duration or size, 1 for the first
+    #[serde(default = "first_part")]
+    pub part: u32,
+}
+fn first_part() -> u32 {
+    1
+}
+/// point in a recording where something changed, muxed as a chapter
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct Chapter {
+    /// seconds into the recording
+    pub start: f64,
+    pub title: String,
+}
+/// temporary file and where it is moved once muxed
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct TempFile {
+    pub path: PathBuf,
+    pub final_path: PathBuf,
+}
+impl TempFile {
+    fn of(file: &ManagedFile) -> Self {
+        Self {
+            path: file.path.clone(),
+            final_path: file.final_path.clone(),
+        }
+    }
+    /// reopens the file for appending, none if it is missing or empty
+    fn open(&self) -> Res<Option<ManagedFile>> {
+        match fs::metadata(&self.path) {
+            Ok(m) if m.len() != 0 => (),
+            _ => return Ok(None),
+        }
+        let mut file =
+            ManagedFile::new(self.path.clone(), self.final_path.clone()).map_err(s!())?;
+        // appended files start at 0, truncating on a reset must not lose earlier data
+        file.file.seek(io::SeekFrom::End(0)).map_err(e!())?;
+        Ok(Some(file))
+    }
+}
+impl Journal {
+    /// starts a journal for newly created temp files
+    pub fn create(
+        playlist: &stream::Playlist,
+        file: &ManagedFile,
+        file_audio: Option<&ManagedFile>,
+        part: u32,
+    ) -> Res<Self> {
+        let mut path = file.path.clone().into_os_string();
+        path.push(".journal.json");
+        let journal = Self {
+            path: path.into(),
+            platform: playlist.platform.clone(),
+            username: playlist.username.clone(),
+            video: TempFile::of(file),
+            audio: file_audio.map(TempFile::of),
+            last_segment: None,
+            sequenced: false,
+            last_date: None,
+            last_duration: 0.0,
+            first_date: None,
+            started: Some(chrono::Local::now().to_rfc3339()),
+            segments: 0,
+            duration: 0.0,
+            missing: 0.0,
+            mp4_header: playlist
+                .mp4_header
+                .as_deref()
+                .map(|h| STANDARD.encode(h.as_slice())),
+            mp4_header_audio: playlist
+                .mp4_header_audio
+                .as_deref()
+                .map(|h| STANDARD.encode(h.as_slice())),
+            gaps: Vec::new(),
+            info: playlist.info.clone(),
+            chapters: Vec::new(),
+            mux: playlist
+                .settings
+                .mux_options(&playlist.platform, &playlist.options),
+            part,
+        };
+        journal.write().map_err(s!())?;
+        Ok(journal)
+    }
+    fn read(path: &Path) -> Res<Self> {
+        let json = fs::read(path).map_err(e!())?;
+        let mut journal: Self = serde_json::from_slice(&json).map_err(e!())?;
+        journal.path = path.to_path_buf();
+        Ok(journal)
+    }
+    /// starts a chapter at the segment with date, the first starts at 0
+    /// timestamps are kept when muxing, so skipped segments count towards the start
+    pub fn chapter(&mut self, title: String, date: Option<chrono::DateTime<chrono::FixedOffset>>) {
+        if self.duration <= 0.0 {
+            return;
+        }
+        if self.chapters.is_empty() {
+            self.chapters.push(Chapter {
+                start: 0.0,
+                title: "start".into(),
+            });
+        }
+        let first = self
+            .first_date
+            .as_deref()
+            .and_then(|d| chrono::DateTime::parse_from_rfc3339(d).ok());
+        let start = match (date, first) {
+            (Some(date), Some(first)) => (date - first).num_milliseconds() as f64 / 1000.0,
+            _ => self.duration + self.missing,
+        };
+        self.chapters.push(Chapter { start, title });
+    }
+    /// EXT-X-PROGRAM-DATE-TIME of the first segment, else when recording started
+    pub fn start(&self) -> Option<String> {
+        self.first_date.clone().or(self.started.clone())
+    }
+    /// writes through a temp file so a crash never leaves a partial journal
+    pub fn write(&self) -> Res<()> {
+        let json = serde_json::to_string(self).map_err(e!())?;
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        fs::write(&temp, json).map_err(e!())?;
+        fs::rename(&temp, &self.path).map_err(e!())?;
+        Ok(())
+    }
+    /// deletes the journal once its recording is finished
+    pub fn remove(self) -> Res<()> {
+        if self.path.exists() {
+            fs::remove_file(&self.path).map_err(e!())?;
+        }
+        Ok(())
+    }
+    /// reopens the temp files, none if no video was written
+    pub fn open(&self) -> Res<Option<(ManagedFile, Option<ManagedFile>)>> {
+        let file = match self.video.open().map_err(s!())? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let file_audio = match self.audio.as_ref() {
+            Some(audio) => audio.open().map_err(s!())?,
+            None => None,
+        };
+        Ok(Some((file, file_audio)))
+    }
+    /// whether a playlist with these segment positions and header continues the recording
+    pub fn continues(&self, first: u64, last: u64, mp4_header: Option<&[u8]>) -> bool {
+        let last_segment = match self.last_segment {
+            Some(o) => o,
+            None => return false,
+        };
+        let header = mp4_header.map(|h| STANDARD.encode(h));
+        header == self.mp4_header && first <= last_segment + 1 && last_segment <= last
+    }
+    /// muxes the temp files into the output folder
+    pub fn finalize(self) -> Res<()> {
+        let _scope = log::scope(&self.platform, &self.username);
+        if let Some((file, file_audio)) = self.open().map_err(s!())? {
+            info!("recovering {}", file.path.display());
+            stream::finish(file, file_audio, &self).map_err(s!())?;
+        }
+        self.remove().map_err(s!())?;
+        Ok(())
+    }
+    /// finalizes in the background, logging errors
+    pub fn finalize_background(self) {
+        thread::spawn(move || {
+            if let Err(e) = self.finalize() {
+                error!("{}", e);
+            }
+        });
+    }
+}
+/// journals found at startup, waiting to be continued or finalized
+static RECOVERED: Mutex<Vec<Journal>> = Mutex::new(Vec::new());
+/// loads journals left in the temp directory by a previous run
+pub fn recover() -> Res<()> {
+    let temp_dir = util::temp_dir().map_err(s!())?;
+    let entries = match fs::read_dir(&temp_dir) {
+        Ok(r) => r,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).map_err(e!())?,
+    };
+    let mut recovered = RECOVERED.lock().map_err(s!())?;
+    for entry in entries {
+        let path = entry.map_err(e!())?.path();
+        if !path.to_string_lossy().ends_with(".journal.json") {
+            continue;
+        }
+        match Journal::read(&path) {
+            Ok(journal) => recovered.push(journal),
+            Err(e) => {
+                error!("{}", e);
+                fs::remove_file(&path).map_err(e!())?;
+            }
+        }
+    }
+    Ok(())
+}
+/// takes the recovered journal of a model
+pub fn take(platform: &Platform, username: &str) -> Option<Journal> {
+    let mut recovered = RECOVERED.lock().ok()?;
+    let i = recovered
+        .iter()
+        .position(|j| &j.platform == platform && j.username == username)?;
+    Some(recovered.swap_remove(i))
+}
+/// finalizes every recovered journal not taken by a recording
+pub fn finalize_remaining() -> Res<()> {
+    let recovered = mem::take(&mut *RECOVERED.lock().map_err(s!())?);
+    if recovered.is_empty() {
+        return Ok(());
+    }
+    thread::spawn(move || {
+        for journal in recovered {
+            if let Err(e) = journal.finalize() {
+                error!("{}", e);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use {
+    crate::{platforms::Platform, util},
+    std::{
+        cell::RefCell,
+        io::Write,
+        path::PathBuf,
+        sync::{Mutex, MutexGuard},
+        *,
+    },
+};
+/// log level, each level includes the ones before it
+#[derive(
+    serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+impl Level {
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+/// logging settings, set in "config.log"
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct LogSettings {
+    pub level: Level,
+    pub format: Format,
+    /// file lines are also written to, rotated when it reaches max-size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    #[serde(with = "util::serde_size")]
+    pub max_size: u64,
+    /// rotated files kept as "<file>.1" to "<file>.<max-files>"
+    pub max_files: u32,
+}
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: Level::Info,
+            format: Format::Text,
+            file: None,
+            max_size: 10 << 20,
+            max_files: 5,
+        }
+    }
+}
+impl LogSettings {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+struct Logger {
+    settings: LogSettings,
+    file: Option<(fs::File, u64)>,
+}
+static LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+fn logger() -> MutexGuard<'static, Option<Logger>> {
+    LOGGER.lock().unwrap_or_else(|e| e.into_inner())
+}
+/// applies logging settings, the DEBUG environment variable raises the level to debug
+pub fn configure(settings: &LogSettings) {
+    let mut settings = settings.clone();
+    if env::var("DEBUG").is_ok() && settings.level < Level::Debug {
+        settings.level = Level::Debug;
+    }
+    let mut logger = logger();
+    if logger.as_ref().is_some_and(|l| l.settings == settings) {
+        return;
+    }
+    let file = settings.file.as_ref().and_then(|path| {
+        let open = fs::OpenOptions::new().create(true).append(true).open(path);
+        match open.and_then(|f| Ok((f.metadata()?.len(), f))) {
+            Ok((size, file)) => Some((file, size)),
+            Err(e) => {
+                eprintln!("cannot open log file {}: {}", path.display(), e);
+                None
+            }
+        }
+    });
+    *logger = Some(Logger { settings, file });
+}
+thread_local! {
+    static CONTEXT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+/// model a thread is working on, added to its log lines
+#[derive(Clone)]
+pub struct Context {
+    platform: Platform,
+    username: String,
+}
+/// restores the previous context when dropped
+pub struct Scope(Option<Context>);
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CONTEXT.with(|c| *c.borrow_mut() = previous);
+    }
+}
+/// sets the model for log lines of this thread until the scope is dropped
+pub fn scope(platform: &Platform, username: &str) -> Scope {
+    enter(Some(Context {
+        platform: platform.clone(),
+        username: username.to_string(),
+    }))
+}
+/// the current context, to carry into spawned threads
+pub fn context() -> Option<Context> {
+    CONTEXT.with(|c| c.borrow().clone())
+}
+/// sets a context taken from another thread
+pub fn enter(context: Option<Context>) -> Scope {
+    Scope(CONTEXT.with(|c| c.replace(context)))
+}
+/// writes a line at level, used by the logging macros
+pub fn write(level: Level, args: fmt::Arguments) {
+    let mut logger = logger();
+    let logger = logger.get_or_insert_with(|| Logger {
+        settings: LogSettings::default(),
+        file: None,
+    });
+    if level > logger.settings.level {
+        return;
+    }
+    let context = context();
+    let time = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false);
+    let line = match logger.settings.format {
+        Format::Text => match context.as_ref() {
+            Some(c) => format!(
+                "{} {:5} [{:?}:{}] {}",
+                time,
+                level.name(),
+                c.platform,
+                c.username,
+                args
+            ),
+            None => format!("{} {:5} {}", time, level.name(), args),
+        },
+        Format::Json => {
+            let mut json = serde_json::json!({
+                "time": time,
+                "level": level.name(),
+                "message": args.to_string(),
+            });
+            if let Some(c) = context.as_ref() {
+                json["platform"] = format!("{:?}", c.platform).into();
+                json["model"] = c.username.clone().into();
+            }
+            json.to_string()
+        }
+    };
+    match level {
+        Level::Error | Level::Warn => eprintln!("{}", line),
+        _ => println!("{}", line),
+    }
+    if let Err(e) = logger.write_file(&line) {
+        eprintln!("cannot write log file: {}", e);
+        logger.file = None;
+    }
+}
+impl Logger {
+    fn write_file(&mut self, line: &str) -> io::Result<()> {
+        let Some((file, size)) = self.file.as_mut() else {
+            return Ok(());
+        };
+        writeln!(file, "{}", line)?;
+        *size += line.len() as u64 + 1;
+        if *size >= self.settings.max_size {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+    /// moves "<file>" to "<file>.1", shifting older files up to max-files
+    fn rotate(&mut self) -> io::Result<()> {
+        let Some(path) = self.settings.file.clone() else {
+            return Ok(());
+        };
+        let numbered = |i: u32| {
+            let mut p = path.clone().into_os_string();
+            p.push(format!(".{}", i));
+            PathBuf::from(p)
+        };
+        if self.settings.max_files == 0 {
+            fs::remove_file(&path)?;
+        } else {
+            for i in (1..self.settings.max_files).rev() {
+                if numbered(i).exists() {
+                    fs::rename(numbered(i), numbered(i + 1))?;
+                }
+            }
+            fs::rename(&path, numbered(1))?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        self.file = Some((file, 0));
+        Ok(())
+    }
+}
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Error, format_args!($($arg)*))
+    };
+}
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Warn, format_args!($($arg)*))
+    };
+}
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Info, format_args!($($arg)*))
+    };
+}
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*))
+    };
+}
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Trace, format_args!($($arg)*))
+    };
+}
@@ -1,36 +1,60 @@
 mod abort;
+mod api;
+mod cli;
 mod config;
 mod err;
+mod history;
+mod hls;
+mod http;
+mod journal;
+mod log;
+mod metrics;
 mod muxer;
 mod platforms;
+mod remux;
+mod status;
 mod stream;
+mod template;
 mod util;
-use std::{
-    path::{PathBuf},
-    time::Duration,
-    *,
-};
+use std::{path::Path, time::Duration, *};
+/// version set at build time
+pub const TAG: Option<&str> = option_env!("TAG");
 
 fn main() {
-    const TAG: Option<&str> = option_env!("TAG");
-    println!("cbstream {}", TAG.unwrap_or_default());
-
-    let filename = PathBuf::from(
-        env::args_os()
-            .nth(1)
-            .or_else(|| env::var_os("CONFIG"))
-            .unwrap_or_else(|| "cb-config.json".into()),
-    );
-
-    let mut models = config::init(&filename).unwrap();
-    while !abort::get().unwrap() {
-        models.download().unwrap();
-        for _ in 0..60 {
-            thread::sleep(Duration::from_secs(1));
-            if abort::get().unwrap() {
-                break;
+    let args = match cli::parse(env::args_os()) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    match args.command {
+        cli::Command::Run => run(&args.config),
+        command => {
+            if let Err(e) = cli::run(&args.config, command) {
+                eprintln!("{}", e);
+                process::exit(1);
             }
-            models.update_config().unwrap();
         }
     }
 }
+/// records every model in the config until stopped
+fn run(filename: &Path) {
+    let mut models = config::init(filename).unwrap();
+    log::configure(&models.settings().log);
+    info!("cbstream {}", TAG.unwrap_or_default());
+    let settings = models.settings();
+    if let Some(address) = settings.api_address.as_deref() {
+        api::start(address, settings.api_token.clone(), filename).unwrap();
+    }
+    journal::recover().unwrap();
+    history::load(&history::path(filename)).unwrap();
+    while !abort::get().unwrap() {
+        // polls the models that are due
+        models.download().unwrap();
+        // recordings of models that did not come back online
+        journal::finalize_remaining().unwrap();
+        thread::sleep(Duration::from_secs(1));
+        models.update_config().unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+use {
+    crate::{
+        platforms::Platform,
+        s,
+        status::{self, State},
+        util,
+    },
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        fmt::Write,
+        path::PathBuf,
+        sync::Mutex,
+        *,
+    },
+};
+type Res<T> = Result<T, crate::err::Error>;
+/// counters since startup, gauges are read when rendered
+#[derive(Default)]
+struct Metrics {
+    /// (platform, result)
+    segments: BTreeMap<(String, &'static str), u64>,
+    bytes_written: BTreeMap<String, u64>,
+    /// (platform, status code)
+    http_requests: BTreeMap<(String, String), u64>,
+    /// (muxer, result) -> (count, seconds)
+    mux: BTreeMap<(&'static str, &'static str), (u64, f64)>,
+}
+static METRICS: Mutex<Option<Metrics>> = Mutex::new(None);
+/// folders recordings are saved under, their free space is reported
+static OUTPUT_ROOTS: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+/// sets the output roots of the platforms and models, the working directory where none is set
+pub fn set_output_roots(roots: BTreeSet<PathBuf>) -> Res<()> {
+    *OUTPUT_ROOTS.lock().map_err(s!())? = roots;
+    Ok(())
+}
+fn with(f: impl FnOnce(&mut Metrics)) {
+    if let Ok(mut metrics) = METRICS.lock() {
+        f(metrics.get_or_insert_with(Metrics::default));
+    }
+}
+fn label(platform: &Platform) -> String {
+    format!("{:?}", platform)
+}
+/// counts a segment written to a recording, or one that failed to download
+pub fn segment(platform: &Platform, downloaded: bool) {
+    let result = if downloaded { "downloaded" } else { "failed" };
+    with(|m| *m.segments.entry((label(platform), result)).or_default() += 1);
+}
+pub fn bytes_written(platform: &Platform, bytes: u64) {
+    with(|m| *m.bytes_written.entry(label(platform)).or_default() += bytes);
+}
+/// counts a request of the platform
+/// status is the http status code or "error" if no response was received
+pub fn http_request(platform: &Platform, status: &str) {
+    with(|m| {
+        *m.http_requests
+            .entry((label(platform), status.to_string()))
+            .or_default() += 1
+    });
+}
+/// records how long muxing took and whether it succeeded
+pub fn mux(muxer: &'static str, success: bool, duration: time::Duration) {
+    let result = if success { "success" } else { "failure" };
+    with(|m| {
+        let (count, seconds) = m.mux.entry((muxer, result)).or_default();
+        *count += 1;
+        *seconds += duration.as_secs_f64();
+    });
+}
+/// metrics in the prometheus text format
+pub fn render() -> Res<String> {
+    let mut out = String::new();
+    // gauges from the model states
+    let mut models: BTreeMap<String, u64> =
+        Platform::list().iter().map(|p| (label(p), 0)).collect();
+    let mut recordings: BTreeMap<String, u64> = models.clone();
+    for model in status::list().map_err(s!())? {
+        *models.entry(label(&model.platform)).or_default() += 1;
+        if matches!(model.state, State::Recording | State::Muxing) {
+            *recordings.entry(label(&model.platform)).or_default() += 1;
+        }
+    }
+    header(&mut out, "models", "gauge", "models tracked");
+    for (platform, n) in models {
+        writeln!(out, "cbstream_models{{platform=\"{}\"}} {}", platform, n)?;
+    }
+    header(&mut out, "recordings", "gauge", "active recordings");
+    for (platform, n) in recordings {
+        writeln!(
+            out,
+            "cbstream_recordings{{platform=\"{}\"}} {}",
+            platform, n
+        )?;
+    }
+    // free space
+    header(
+        &mut out,
+        "free_bytes",
+        "gauge",
+        "free space at the temp directory and the output roots",
+    );
+    let mut paths = vec![("temp", util::temp_dir().map_err(s!())?)];
+    let roots = OUTPUT_ROOTS.lock().map_err(s!())?.clone();
+    paths.extend(roots.into_iter().map(|root| ("output", root)));
+    let current_dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    for (kind, path) in paths {
+        // roots that do not exist yet are measured at their absolute path
+        let path = fs::canonicalize(&path).unwrap_or_else(|_| current_dir.join(path));
+        if let Some(available) = util::available_space_for_path(&path) {
+            writeln!(
+                out,
+                "cbstream_free_bytes{{kind=\"{}\",path=\"{}\"}} {}",
+                kind,
+                escape(&path.to_string_lossy()),
+                available
+            )?;
+        }
+    }
+    // counters
+    let metrics = METRICS.lock().map_err(s!())?;
+    let default = Metrics::default();
+    let metrics = metrics.as_ref().unwrap_or(&default);
+    header(&mut out, "segments_total", "counter", "segments by result");
+    for ((platform, result), n) in &metrics.segments {
+        writeln!(
+            out,
+            "cbstream_segments_total{{platform=\"{}\",result=\"{}\"}} {}",
+            platform, result, n
+        )?;
+    }
+    header(
+        &mut out,
+        "bytes_written_total",
+        "counter",
+        "bytes written to recordings",
+    );
+    for (platform, n) in &metrics.bytes_written {
+        writeln!(
+            out,
+            "cbstream_bytes_written_total{{platform=\"{}\"}} {}",
+            platform, n
+        )?;
+    }
+    header(
+        &mut out,
+        "http_requests_total",
+        "counter",
+        "http requests by platform and status code",
+    );
+    for ((platform, code), n) in &metrics.http_requests {
+        writeln!(
+            out,
+            "cbstream_http_requests_total{{platform=\"{}\",code=\"{}\"}} {}",
+            platform, code, n
+        )?;
+    }
+    header(
+        &mut out,
+        "mux_duration_seconds",
+        "summary",
+        "time spent muxing recordings",
+    );
+    for ((muxer, result), (count, seconds)) in &metrics.mux {
+        let labels = format!("muxer=\"{}\",result=\"{}\"", muxer, result);
+        writeln!(
+            out,
+            "cbstream_mux_duration_seconds_sum{{{}}} {}",
+            labels, seconds
+        )?;
+        writeln!(
+            out,
+            "cbstream_mux_duration_seconds_count{{{}}} {}",
+            labels, count
+        )?;
+    }
+    Ok(out)
+}
+/// label value with backslashes, quotes and newlines escaped, ex. windows paths
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP cbstream_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE cbstream_{} {}", name, kind);
+}
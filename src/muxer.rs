@@ -1,14 +1,108 @@
 use {
     crate::{
-        e, h, o,
-        platforms::Platform,
-        s,
+        e,
+        err::Error,
+        h,
+        journal::{Chapter, Journal},
+        metrics, o,
+        platforms::{Container, Platform},
+        remux, s,
         util::{self, ManagedFile},
+        warn,
     },
     std::{io::Read, process::ExitStatus, *},
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
-type Hres<T> = Result<T, String>;
+type Res<T> = Result<T, crate::err::Error>;
+/// container recordings are muxed to
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    #[default]
+    Mkv,
+    /// with the index at the start of the file
+    Mp4,
+    Ts,
+    /// the downloaded segments without ffmpeg, separate audio is a ".m4a"
+    KeepRaw,
+}
+impl OutputFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Mkv => "mkv",
+            OutputFormat::Mp4 => "mp4",
+            OutputFormat::Ts => "ts",
+            OutputFormat::KeepRaw => "",
+        }
+    }
+}
+/// how a recording is muxed, chosen when it starts
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct MuxOptions {
+    pub format: OutputFormat,
+    /// ffmpeg arguments run on the muxed file
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub profile: Vec<String>,
+}
+/// container tags and chapters of a recording, written by ffmpeg
+pub struct Tags {
+    tags: Vec<(&'static str, String)>,
+    chapters: Vec<Chapter>,
+    /// seconds, where the last chapter ends
+    duration: f64,
+}
+impl Tags {
+    pub fn of(journal: &Journal) -> Self {
+        let start = journal.start();
+        let date = start
+            .as_deref()
+            .and_then(|d| d.get(..10))
+            .unwrap_or_default();
+        let mut tags = vec![
+            ("title", format!("{} {}", journal.username, date)),
+            ("artist", journal.username.clone()),
+            ("platform", journal.platform.name().to_string()),
+            ("url", journal.platform.room_url(&journal.username)),
+        ];
+        if let Some(start) = start {
+            tags.push(("date", start));
+        }
+        Self {
+            tags,
+            chapters: journal.chapters.clone(),
+            duration: journal.duration,
+        }
+    }
+    /// ffmetadata file of the chapters, none without chapters
+    fn write_chapters(&self, path: &path::Path) -> Res<bool> {
+        if self.chapters.is_empty() {
+            return Ok(false);
+        }
+        let mut text = String::from(";FFMETADATA1\n");
+        for (i, chapter) in self.chapters.iter().enumerate() {
+            let end = self.chapters.get(i + 1).map_or(self.duration, |c| c.start);
+            text.push_str(&format!(
+                "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+                (chapter.start * 1000.0) as u64,
+                (end * 1000.0) as u64,
+                escape(&chapter.title)
+            ));
+        }
+        fs::write(path, text).map_err(e!())?;
+        Ok(true)
+    }
+}
+/// escapes the characters ffmetadata files give a meaning
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
 fn ffmpeg_exists() -> Res<Option<&'static str>> {
     let path = "ffmpeg";
     match process::Command::new(path).arg("-version").output() {
@@ -16,26 +110,34 @@ fn ffmpeg_exists() -> Res<Option<&'static str>> {
         Err(_) => Ok(None),
     }
 }
+/// container of the recorded video, combined audio and video are mp4
+fn container(file: &ManagedFile, file_audio: &Option<ManagedFile>, pf: &Platform) -> Container {
+    match (file_audio, pf.container()) {
+        (Some(_), _) => Container::Mp4,
+        (None, Container::Probe) => {
+            // mpeg-ts packets start with a sync byte
+            let mut first = [0u8];
+            match fs::File::open(&file.path).and_then(|mut f| f.read_exact(&mut first)) {
+                Ok(_) if first[0] == 0x47 => Container::MpegTs,
+                _ => Container::Mp4,
+            }
+        }
+        (None, container) => container,
+    }
+}
 /// muxes streams with ffmpeg pipe
 fn ffmpeg_seperate_v_a(
     ffmpeg_path: &str,
     file: &ManagedFile,
     file_audio: &Option<ManagedFile>,
     pf: &Platform,
-) -> Res<()> {
-    let mut filepath = file.final_path.clone();
-    filepath.set_extension("mkv");
-    let mut container_type = match pf {
-        Platform::CB => "mpegts",
-        Platform::MFC => "mpegts",
-        Platform::SC => "mp4",
-        Platform::SCVR => "mp4",
-        Platform::BONGA => "mpegts",
-        Platform::SODA => "mp4",
-    };
-    if file_audio.is_some() {
-        container_type = "mp4";
-    }
+    tags: &Tags,
+    format: OutputFormat,
+) -> Res<path::PathBuf> {
+    let filepath =
+        util::create_valid_path(&file.final_path.with_added_extension(format.extension()))
+            .map_err(s!())?;
+    let container_type = container(file, file_audio, pf).format();
     // starts ffmpeg process
     let mut command = process::Command::new(ffmpeg_path);
     command
@@ -50,14 +152,65 @@ fn ffmpeg_seperate_v_a(
             .arg("-i")
             .arg(&file_audio.path);
     }
-    let mut child = command
+    let mut chapters_path = file.path.clone().into_os_string();
+    chapters_path.push(".chapters.txt");
+    let chapters_path = path::PathBuf::from(chapters_path);
+    // removed however muxing ends
+    let _chapters = scopeguard::guard(chapters_path.clone(), |path| {
+        let _ = fs::remove_file(path);
+    });
+    // mpeg-ts has no chapters
+    if format != OutputFormat::Ts && tags.write_chapters(&chapters_path).map_err(s!())? {
+        let input = 1 + file_audio.is_some() as usize;
+        command
+            .arg("-f")
+            .arg("ffmetadata")
+            .arg("-i")
+            .arg(&chapters_path)
+            .arg("-map_chapters")
+            .arg(input.to_string());
+    }
+    for (key, value) in &tags.tags {
+        command.arg("-metadata").arg(format!("{}={}", key, value));
+    }
+    if format == OutputFormat::Mp4 {
+        // index at the start so playback can begin while the file is read, keeps custom tags
+        command.arg("-movflags").arg("+faststart+use_metadata_tags");
+    }
+    command
         .arg("-c")
         .arg("copy")
         .arg("-copyts")
         .arg("-avoid_negative_ts")
         .arg("make_zero")
         .arg("-y")
-        .arg(&filepath)
+        .arg(&filepath);
+    run(command, filepath)
+}
+/// runs a profile's ffmpeg arguments on a muxed file, replacing it
+fn ffmpeg_profile(ffmpeg_path: &str, filepath: &path::Path, profile: &[String]) -> Res<()> {
+    let extension = filepath.extension().unwrap_or_default().to_string_lossy();
+    let output = filepath.with_extension(format!("profile.{}", extension));
+    let mut command = process::Command::new(ffmpeg_path);
+    command
+        .arg("-i")
+        .arg(filepath)
+        .args(profile)
+        .arg("-y")
+        .arg(&output);
+    let output = match run(command, output.clone()) {
+        Ok(output) => output,
+        Err(e) => {
+            let _ = fs::remove_file(&output);
+            return Err(e).map_err(s!());
+        }
+    };
+    fs::rename(output, filepath).map_err(e!())?;
+    Ok(())
+}
+/// runs ffmpeg writing output, removing it if memory runs low
+fn run(mut command: process::Command, filepath: path::PathBuf) -> Res<path::PathBuf> {
+    let mut child = command
         .stderr(process::Stdio::piped())
         .stdout(process::Stdio::piped())
         .spawn()
@@ -65,18 +218,18 @@ fn ffmpeg_seperate_v_a(
     // read from stderr/stdout pipes
     let mut stdout = child.stdout.take().ok_or_else(o!())?;
     let mut stderr = child.stderr.take().ok_or_else(o!())?;
-    let stdout_handle = thread::spawn(move || -> Hres<String> {
+    let stdout_handle = thread::spawn(move || -> Res<String> {
         let mut out = String::new();
         stdout.read_to_string(&mut out).map_err(e!())?;
         Ok(out)
     });
-    let stderr_handle = thread::spawn(move || -> Hres<String> {
+    let stderr_handle = thread::spawn(move || -> Res<String> {
         let mut out = String::new();
         stderr.read_to_string(&mut out).map_err(e!())?;
         Ok(out)
     });
     // monitors system memory
-    let kill_handle = thread::spawn(move || -> Hres<ExitStatus> {
+    let kill_handle = thread::spawn(move || -> Res<(ExitStatus, path::PathBuf)> {
         let mut sys = sysinfo::System::new_all();
         let exit_status = loop {
             match child.try_wait().map_err(e!())? {
@@ -93,50 +246,109 @@ fn ffmpeg_seperate_v_a(
             }
             thread::sleep(time::Duration::from_millis(200));
         };
-        Ok(exit_status)
+        Ok((exit_status, filepath))
     });
     // cleanup
-    let exit_status = kill_handle.join().map_err(h!())?.map_err(s!())?;
+    let (exit_status, filepath) = kill_handle.join().map_err(h!())?.map_err(s!())?;
     let stdout = stdout_handle.join().map_err(h!())?.map_err(s!())?;
     let stderr = stderr_handle.join().map_err(h!())?.map_err(s!())?;
     // processes output
     if !exit_status.success() {
-        return Err(format!("{}{}", stdout.trim(), stderr.trim())).map_err(s!())?;
+        let output = format!("{}{}", stdout.trim(), stderr.trim());
+        return Err(Error::MuxFailed(output)).map_err(s!());
     }
-    return Ok(());
+    Ok(filepath)
 }
-/// Main Muxing Function
-pub fn muxer(file: ManagedFile, file_audio: Option<ManagedFile>, pf: Platform) -> Res<()> {
+/// Main Muxing Function, returns the path of the muxed file and the muxer used
+pub fn muxer(
+    file: ManagedFile,
+    file_audio: Option<ManagedFile>,
+    pf: Platform,
+    tags: &Tags,
+    options: &MuxOptions,
+) -> Res<(path::PathBuf, &'static str)> {
     util::create_dir(file.final_path.parent().ok_or_else(o!())?).map_err(s!())?;
-    if let Some(ffmpeg_path) = ffmpeg_exists().map_err(s!())? {
-        match ffmpeg_seperate_v_a(ffmpeg_path, &file, &file_audio, &pf) {
-            Err(e) => eprintln!("{}", e),
-            Ok(_) => return Ok(()),
+    if options.format != OutputFormat::KeepRaw
+        && let Some(ffmpeg_path) = ffmpeg_exists().map_err(s!())?
+    {
+        let start = time::Instant::now();
+        let result =
+            ffmpeg_seperate_v_a(ffmpeg_path, &file, &file_audio, &pf, tags, options.format);
+        metrics::mux("ffmpeg", result.is_ok(), start.elapsed());
+        match result {
+            Err(e) => warn!("{}", e),
+            Ok(filepath) => {
+                if !options.profile.is_empty() {
+                    let start = time::Instant::now();
+                    let result = ffmpeg_profile(ffmpeg_path, &filepath, &options.profile);
+                    metrics::mux("profile", result.is_ok(), start.elapsed());
+                    if let Err(e) = result {
+                        warn!("{}, keeping the copy", e);
+                    }
+                }
+                return Ok((filepath, "ffmpeg"));
+            }
         }
     }
-    local_muxer(file, file_audio, pf).map_err(s!())?;
-    Ok(())
+    // ts is kept unless mp4 was asked for, fragmented mp4 is always merged
+    let container = container(&file, &file_audio, &pf);
+    let native = match container {
+        Container::MpegTs => options.format == OutputFormat::Mp4,
+        _ => options.format != OutputFormat::KeepRaw,
+    };
+    if native {
+        let start = time::Instant::now();
+        let result = native_muxer(&file, &file_audio, container, tags.duration);
+        metrics::mux("native", result.is_ok(), start.elapsed());
+        match result {
+            Ok(filepath) => return Ok((filepath, "native")),
+            Err(e) => warn!("{}", e),
+        }
+    }
+    let start = time::Instant::now();
+    let result = local_muxer(file, file_audio, pf);
+    metrics::mux("local", result.is_ok(), start.elapsed());
+    let filepath = result.map_err(s!())?;
+    Ok((filepath, "local"))
 }
-/// Fallback local muxer
-fn local_muxer(file: ManagedFile, file_audio: Option<ManagedFile>, pf: Platform) -> Res<()> {
-    let mut extension = match pf {
-        Platform::CB => "ts",
-        Platform::MFC => "ts",
-        Platform::SC => "mp4",
-        Platform::SCVR => "mp4",
-        Platform::BONGA => "ts",
-        Platform::SODA => "mp4",
+/// remuxes to mp4 without ffmpeg, merging separate audio
+fn native_muxer(
+    file: &ManagedFile,
+    file_audio: &Option<ManagedFile>,
+    container: Container,
+    duration: f64,
+) -> Res<path::PathBuf> {
+    let filepath =
+        util::create_valid_path(&file.final_path.with_added_extension("mp4")).map_err(s!())?;
+    let result = match container {
+        Container::MpegTs => remux::convert(&file.path, &filepath),
+        _ => {
+            let mut inputs = vec![file.path.as_path()];
+            inputs.extend(file_audio.as_ref().map(|f| f.path.as_path()));
+            remux::merge(&inputs, &filepath, duration)
+        }
     };
-    if file_audio.is_some() {
-        extension = "mp4";
+    if let Err(e) = result {
+        let _ = fs::remove_file(&filepath);
+        return Err(e).map_err(s!());
     }
-    let mut filepath = file.final_path.clone();
-    filepath.set_extension(extension);
-    file.mv(&filepath).map_err(s!())?;
+    Ok(filepath)
+}
+/// Fallback local muxer
+fn local_muxer(
+    file: ManagedFile,
+    file_audio: Option<ManagedFile>,
+    pf: Platform,
+) -> Res<path::PathBuf> {
+    let extension = container(&file, &file_audio, &pf).extension();
+    let filepath = file
+        .mv(&file.final_path.with_added_extension(extension))
+        .map_err(s!())?;
     if let Some(file_audio) = file_audio.as_ref() {
-        let mut filepath_audio = file_audio.final_path.clone();
-        filepath_audio.set_extension("m4a");
-        file_audio.mv(&filepath_audio).map_err(s!())?;
+        // named after the video, which may have been renamed to avoid overwriting a file
+        file_audio
+            .mv(&filepath.with_extension("m4a"))
+            .map_err(s!())?;
     }
-    Ok(())
+    Ok(filepath)
 }
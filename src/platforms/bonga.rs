@@ -1,15 +1,43 @@
 use {
-    crate::{config::Settings, e, o, platforms::Platform, s, stream, util},
+    crate::{
+        config::Settings,
+        e, hls, o,
+        platforms::{Container, ModelOptions, Platform, Provider, Room, RoomState},
+        s, stream, util,
+    },
     std::{sync::Arc, *},
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
-pub fn get_playlist(
-    username: &str,
-    settings: Arc<Settings>,
-) -> Res<(Option<String>, Option<String>)> {
+type Res<T> = Result<T, crate::err::Error>;
+pub struct Bonga;
+impl Provider for Bonga {
+    fn name(&self) -> &'static str {
+        "BONGA"
+    }
+    fn referer(&self) -> &'static str {
+        "https://bongacams.com/"
+    }
+    fn filename_prefix(&self) -> &'static str {
+        "BC"
+    }
+    fn container(&self) -> Container {
+        Container::MpegTs
+    }
+    fn get_playlist(
+        &self,
+        username: &str,
+        options: &ModelOptions,
+        settings: Arc<Settings>,
+    ) -> Res<Room> {
+        get_playlist(username, options, settings)
+    }
+    fn parse_playlist(&self, playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+        parse_playlist(playlist)
+    }
+}
+fn get_playlist(username: &str, options: &ModelOptions, settings: Arc<Settings>) -> Res<Room> {
     let headers = util::create_headers(serde_json::json!({
         "user-agent": (&settings.user_agent).to_lowercase(),
-        "referer": format!("{}{}",Platform::BONGA.referer(),username),
+        "referer": format!("{}{}",Platform(&Bonga).referer(),username),
         "x-requested-with": "XMLHttpRequest",
     }))
     .map_err(s!())?;
@@ -26,6 +54,7 @@ pub fn get_playlist(
         username
     );
     let json_raw = util::post_retry(
+        &Platform(&Bonga),
         &url,
         1,
         Some(&headers),
@@ -42,7 +71,7 @@ pub fn get_playlist(
         .as_str()
     {
         Some(o) => o,
-        None => return Ok((None, None)),
+        None => return Ok(Room::new(RoomState::Offline)),
     };
     let performer_data = json.get("performerData").ok_or_else(o!())?;
     if !performer_data
@@ -51,7 +80,7 @@ pub fn get_playlist(
         .as_bool()
         .ok_or_else(o!())?
     {
-        return Ok((None, None));
+        return Ok(Room::new(RoomState::Offline));
     }
     if performer_data
         .get("isAway")
@@ -59,7 +88,12 @@ pub fn get_playlist(
         .as_bool()
         .ok_or_else(o!())?
     {
-        return Ok((None, None));
+        return Ok(Room::new(RoomState::Away));
+    }
+    if let Some("private" | "group" | "fullprivate") =
+        performer_data.get("showType").and_then(|s| s.as_str())
+    {
+        return Ok(Room::new(RoomState::Private));
     }
     let playlist_url = format!(
         "https:{}/hls/stream_{}/playlist.m3u8",
@@ -71,49 +105,33 @@ pub fn get_playlist(
             .ok_or_else(o!())?
     );
     // get playlist of resolutions
-    let playlist = util::get_retry(&playlist_url, 1, Some(&headers)).map_err(s!())?;
-    for line in playlist.lines().rev() {
-        if line.len() < 5 || &line[..1] == "#" {
-            continue;
-        }
-        let playlist_link = Some(format!(
-            "{}/{}",
-            util::url_prefix(&playlist_url, line).ok_or_else(o!())?,
-            line
-        ));
-        return Ok((playlist_link, None));
-    }
-    Ok((None, None))
+    let playlist =
+        util::get_retry(&Platform(&Bonga), &playlist_url, 1, Some(&headers)).map_err(s!())?;
+    let preference = settings.variant_preference(&Platform(&Bonga), options);
+    let master = hls::MasterPlaylist::parse(&playlist);
+    let variant = match master.select(&preference) {
+        Some(o) => o,
+        None => return Ok(Room::public(None, None)),
+    };
+    let playlist_link = Some(format!(
+        "{}/{}",
+        util::url_prefix(&playlist_url, &variant.uri).ok_or_else(o!())?,
+        variant.uri
+    ));
+    Ok(Room::public(playlist_link, None).variant(Some(variant)))
 }
-pub fn parse_playlist(playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+fn parse_playlist(playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+    let media = playlist.media(false).map_err(s!())?;
+    //parse filenames
+    let filename = playlist.filename(&media);
     let mut streams = Vec::new();
-    for line in playlist.playlist.as_ref().ok_or_else(o!())?.lines() {
-        if line.len() == 0 || &line[..1] == "#" {
-            continue;
-        }
-        // parse relevant information
-        let url = format!(
-            "{}/{}",
-            util::url_prefix(&playlist.playlist_url, line).ok_or_else(o!())?,
-            line
-        );
+    for segment in &media.segments {
         // parse stream id
-        let id_split = line.split("_").collect::<Vec<&str>>();
+        let name = stream::Playlist::segment_name(&segment.uri);
+        let id_split = name.split("_").collect::<Vec<&str>>();
         let id_raw = *id_split.get(3).ok_or_else(o!())?;
-        let id = util::remove_non_num(id_raw).parse::<u32>().map_err(e!())?;
-        //parse filenames
-        let date = util::date();
-        let filename = format!("BC_{}_{}", playlist.username, date);
-        streams.push(stream::Stream::new(
-            &filename,
-            &url,
-            None,
-            id,
-            Platform::BONGA,
-            playlist.settings.user_agent.clone(),
-            None,
-            None,
-        ));
+        let id = util::remove_non_num(id_raw).parse::<u64>().map_err(e!())?;
+        streams.push(stream::Stream::new(playlist, &filename, id, segment, None));
     }
     Ok(streams)
 }
@@ -1,251 +1,207 @@
 use {
-    crate::{config::Settings, debug_eprintln, e, o, platforms::Platform, s, stream, util},
-    std::{
-        collections::HashMap,
-        sync::{Arc, OnceLock},
-        *,
+    crate::{
+        config::Settings,
+        debug, e,
+        err::Error,
+        hls, o,
+        platforms::{Container, ModelOptions, Platform, Provider, Room, RoomState},
+        s, stream, util,
     },
+    std::{collections::HashMap, sync::Arc, *},
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
-
-static REGEX_AUDIO_MATCH: OnceLock<Arc<regex::Regex>> = OnceLock::new();
+type Res<T> = Result<T, crate::err::Error>;
+pub struct Cb;
+impl Provider for Cb {
+    fn name(&self) -> &'static str {
+        "CB"
+    }
+    fn referer(&self) -> &'static str {
+        "https://chaturbate.com/"
+    }
+    fn container(&self) -> Container {
+        Container::MpegTs
+    }
+    fn get_playlist(
+        &self,
+        username: &str,
+        options: &ModelOptions,
+        settings: Arc<Settings>,
+    ) -> Res<Room> {
+        get_playlist(username, options, settings)
+    }
+    fn rooms(&self, settings: &Settings) -> Option<Res<HashMap<String, RoomState>>> {
+        let affiliate_id = settings.for_platform(&Platform(&Cb)).affiliate_id?;
+        Some(online_rooms(&affiliate_id, settings))
+    }
+    fn parse_playlist(&self, playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+        parse_playlist(playlist)
+    }
+}
+/// rooms listed by the affiliate api, a page at a time
+fn online_rooms(affiliate_id: &str, settings: &Settings) -> Res<HashMap<String, RoomState>> {
+    const LIMIT: usize = 500;
+    let headers = Platform(&Cb).headers(&settings.user_agent).map_err(s!())?;
+    let mut rooms = HashMap::new();
+    for page in 0.. {
+        let url = format!(
+            "https://chaturbate.com/api/public/affiliates/onlinerooms/?wm={}&client_ip=request_ip&limit={}&offset={}",
+            affiliate_id,
+            LIMIT,
+            page * LIMIT
+        );
+        let json_raw = util::get_retry(&Platform(&Cb), &url, 2, Some(&headers)).map_err(s!())?;
+        let json: serde_json::Value = serde_json::from_str(&json_raw).map_err(e!())?;
+        let results = json
+            .get("results")
+            .and_then(|r| r.as_array())
+            .ok_or_else(o!())?;
+        for room in results {
+            let username = room.get("username").and_then(|u| u.as_str());
+            let show = room.get("current_show").and_then(|s| s.as_str());
+            if let (Some(username), Some(show)) = (username, show) {
+                let state = room_state(show).unwrap_or(RoomState::Public);
+                rooms.insert(username.to_lowercase(), state);
+            }
+        }
+        let count = json.get("count").and_then(|c| c.as_u64()).unwrap_or(0);
+        if results.len() < LIMIT || rooms.len() as u64 >= count {
+            break;
+        }
+    }
+    Ok(rooms)
+}
+/// state of a room status that is not public
+fn room_state(status: &str) -> Option<RoomState> {
+    match status {
+        "private" | "group" | "password protected" => Some(RoomState::Private),
+        "away" => Some(RoomState::Away),
+        "hidden" => Some(RoomState::Hidden),
+        "offline" => Some(RoomState::Offline),
+        _ => None,
+    }
+}
 
-pub fn get_playlist(
-    username: &str,
-    settings: Arc<Settings>,
-) -> Res<(Option<String>, Option<String>)> {
+fn get_playlist(username: &str, options: &ModelOptions, settings: Arc<Settings>) -> Res<Room> {
     let username = username.to_lowercase();
     let headers = util::create_headers(serde_json::json!({
         "user-agent": &settings.user_agent,
-        "referer": format!("{}{}",Platform::CB.referer(),username),
+        "referer": format!("{}{}",Platform(&Cb).referer(),username),
 
     }))
     .map_err(s!())?;
     // get model playlist link
     let url = format!("https://chaturbate.com/api/chatvideocontext/{}/", username);
-    let json_raw = match util::get_retry(&url, 1, Some(&headers)) {
-        Ok(r) => Ok::<String, Box<dyn error::Error>>(r),
-        Err(e) => {
-            if e.to_string().contains("Unauthorized") {
-                debug_eprintln!("{}", e);
-                return Ok((None, None));
+    let json_raw = match util::get_retry(&Platform(&Cb), &url, 1, Some(&headers)) {
+        Ok(r) => r,
+        // password protected or banned rooms are unauthorized
+        Err(e) if e.status() == Some(401) => {
+            debug!("{}", e);
+            if matches!(e.root(), Error::Http { body, .. } if body.contains("banned")) {
+                return Ok(Room::new(RoomState::Banned));
             }
-            if e.to_string().contains("404 Not Found") {
-                eprintln!("CB user {} not found", username);
-            }
-            Err(e)
+            return Ok(Room::new(RoomState::Private));
         }
-    }
-    .map_err(s!())?;
+        Err(e) if e.status() == Some(404) => {
+            return Err(Error::NotFound(username)).map_err(s!());
+        }
+        Err(e) => return Err(e).map_err(s!()),
+    };
     let json: serde_json::Value = serde_json::from_str(&json_raw).map_err(e!())?;
     let playlist_url = json
         .get("hls_source")
         .ok_or_else(o!())?
         .as_str()
         .ok_or_else(o!())?;
-    if playlist_url.len() == 0 {
-        return Ok((None, None));
+    let state = match json
+        .get("room_status")
+        .and_then(|s| room_state(s.as_str()?))
+    {
+        Some(state) => state,
+        None if playlist_url.is_empty() => RoomState::Offline,
+        None => RoomState::Public,
+    };
+    if state != RoomState::Public {
+        return Ok(Room::new(state));
     }
     // get playlist of resolutions
-    let playlist = util::get_retry(&playlist_url, 1, Some(&headers)).map_err(s!())?;
-    let playlist_audio_url = if playlist.contains("audio") {
-        let re: &Arc<regex::Regex> = REGEX_AUDIO_MATCH.get_or_init(|| {
-            regex::Regex::new(r#"audio_aac_.*?URI="([^"]+)""#)
-                .unwrap()
-                .into()
-        });
-        if let Some(audio_uri) = re
-            .captures(&playlist)
-            .and_then(|c| Some(c.get(1)?.as_str()))
-        {
-            let prefix = util::url_prefix(playlist_url, audio_uri).ok_or_else(o!())?;
-            Some(format!("{}{}", prefix, audio_uri))
-        } else {
-            None
-        }
+    let playlist =
+        util::get_retry(&Platform(&Cb), playlist_url, 1, Some(&headers)).map_err(s!())?;
+    let master = hls::MasterPlaylist::parse(&playlist);
+    let preference = settings.variant_preference(&Platform(&Cb), options);
+    let variant = match master.select(&preference) {
+        Some(o) => o,
+        None => return Ok(Room::public(None, None)),
+    };
+    let playlist_audio_url = match master.audio_for(variant).and_then(|r| r.uri.as_deref()) {
+        Some(audio_uri) => Some(format!(
+            "{}{}",
+            util::url_prefix(playlist_url, audio_uri).ok_or_else(o!())?,
+            audio_uri
+        )),
+        None => None,
+    };
+    let space = if playlist_audio_url.is_some() {
+        ""
     } else {
-        None
+        "/"
     };
-    for line in playlist.lines().rev() {
-        if line.len() < 5 || &line[..1] == "#" {
-            continue;
-        }
-        let space = if playlist_audio_url.is_some() {
-            ""
-        } else {
-            "/"
-        };
-        let playlist_url = Some(format!(
-            "{}{}{}",
-            util::url_prefix(playlist_url, line).ok_or_else(o!())?,
-            space,
-            line
-        ));
-        return Ok((playlist_url, playlist_audio_url));
-    }
-    Ok((None, None))
+    let playlist_url = Some(format!(
+        "{}{}{}",
+        util::url_prefix(playlist_url, &variant.uri).ok_or_else(o!())?,
+        space,
+        variant.uri
+    ));
+    Ok(Room::public(playlist_url, playlist_audio_url).variant(Some(variant)))
 }
 // parse legacy playlist
-pub fn parse_playlist(playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+fn parse_playlist(playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
     if playlist.playlist_audio_url.is_some() {
         return combine_playlist_audio_video(playlist);
     }
+    let media = playlist.media(false).map_err(s!())?;
+    let filename = playlist.filename(&media);
     let mut streams = Vec::new();
-    let mut date: Option<String> = None;
-    for line in (playlist.playlist.as_ref()).ok_or_else(o!())?.lines() {
-        // parse date and time
-        if date.is_none() {
-            if let Some(n) = line.find("TIME") {
-                if line.len() < 21 {
-                    return Err("error parsing date from playlist")?;
-                }
-                let t = (&line.get(n + 7..n + 21).ok_or_else(o!())?)
-                    .replace(":", "-")
-                    .replace("T", "_");
-                date = Some(t);
-            }
-        }
-        if line.len() == 0 || &line[..1] == "#" {
-            continue;
-        }
-        let full_url = format!(
-            "{}/{}",
-            util::url_prefix(&playlist.playlist_url, line).ok_or_else(o!())?,
-            line
-        );
+    for segment in &media.segments {
         // parse stream id
-        let id = line.split("_").last().ok_or_else(o!())?;
+        let id = stream::Playlist::segment_name(&segment.uri)
+            .split("_")
+            .last()
+            .ok_or_else(o!())?;
         let n = id.find(".").ok_or_else(o!())?;
-        let id = (&id[..n]).trim().parse::<u32>().map_err(e!())?;
-        // parse filenames
-        let date = date.as_ref().ok_or_else(o!())?;
-        let filename = format!("CB_{}_{}", playlist.username, date);
-        streams.push(stream::Stream::new(
-            &filename,
-            &full_url,
-            None,
-            id,
-            Platform::CB,
-            playlist.settings.user_agent.clone(),
-            None,
-            None,
-        ));
+        let id = id[..n].trim().parse::<u64>().map_err(e!())?;
+        streams.push(stream::Stream::new(playlist, &filename, id, segment, None));
     }
-
     Ok(streams)
 }
 fn combine_playlist_audio_video(playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+    let video = playlist.media(false).map_err(s!())?;
+    let audio = playlist.media(true).map_err(s!())?;
+    let filename = playlist.filename(&video);
+    let mut audio_segments: HashMap<u64, &hls::MediaSegment> = HashMap::new();
+    for segment in &audio.segments {
+        audio_segments.insert(split_segment_id(&segment.uri).map_err(s!())?, segment);
+    }
     let mut streams = Vec::new();
-    let video_streams = parse_playlist_audio_video(playlist, false).map_err(s!())?;
-    let audio_streams = parse_playlist_audio_video(playlist, true).map_err(s!())?;
-    for (id, info) in video_streams {
-        let key = id + 1;
-        if audio_streams.contains_key(&key) {
-            let filename = info.filename.as_str();
-            let video_url = info.url.as_str();
-            let audio_url = Some(audio_streams[&key].url.as_str());
-            let new_stream = stream::Stream::new(
-                filename,
-                video_url,
-                audio_url,
+    for segment in &video.segments {
+        let id = split_segment_id(&segment.uri).map_err(s!())?;
+        // audio segments are numbered one ahead of their video segment
+        if let Some(segment_audio) = audio_segments.get(&(id + 1)) {
+            streams.push(stream::Stream::new(
+                playlist,
+                &filename,
                 id,
-                Platform::CB,
-                playlist.settings.user_agent.clone(),
-                playlist.mp4_header.clone(),
-                playlist.mp4_header_audio.clone(),
-            );
-            streams.push(new_stream);
+                segment,
+                Some(segment_audio),
+            ));
         }
     }
     Ok(streams)
 }
-struct Info {
-    url: String,
-    filename: String,
-}
-fn parse_playlist_audio_video(
-    playlist: &mut stream::Playlist,
-    audio: bool,
-) -> Res<HashMap<u32, Info>> {
-    let mut date: Option<String> = None;
-    let mut streams: HashMap<u32, Info> = HashMap::new();
-    let playlist_text = if audio {
-        playlist.playlist_audio.as_ref().ok_or_else(o!())?
-    } else {
-        playlist.playlist.as_ref().ok_or_else(o!())?
-    };
-    let playlist_mp4_header = if audio {
-        &mut playlist.mp4_header_audio
-    } else {
-        &mut playlist.mp4_header
-    };
-    let playlist_url: &str = if audio {
-        playlist.playlist_audio_url.as_ref().ok_or_else(o!())?
-    } else {
-        playlist.playlist_url.as_ref()
-    };
-    for line in playlist_text.lines() {
-        // parse MP4 header
-        if playlist_mp4_header.is_none() {
-            if line.contains("EXT-X-MAP:URI") {
-                // parse header url
-                let header_url_split = line.split("\"").collect::<Vec<&str>>();
-                if header_url_split.len() < 2 {
-                    return Err("can not get mp4 header file".into());
-                }
-                let header_url = format!(
-                    "{}{}",
-                    util::url_prefix(playlist_url, header_url_split[1]).ok_or_else(o!())?,
-                    header_url_split[1]
-                );
-                let http_headers = util::create_headers(serde_json::json!({
-                    "user-agent": &playlist.settings.user_agent,
-                    "referer": Platform::CB.referer(),
-
-                }))
-                .map_err(s!())?;
-                let header =
-                    util::get_retry_vec(&header_url, 5, Some(&http_headers)).map_err(s!())?;
-                *playlist_mp4_header = Some(sync::Arc::new(header))
-            }
-        }
-        // parse date and time
-        if date.is_none() {
-            if let Some(n) = line.find("TIME") {
-                if line.len() < 21 {
-                    return Err("error parsing date from playlist")?;
-                }
-                let t = (&line.get(n + 7..n + 21).ok_or_else(o!())?)
-                    .replace(":", "-")
-                    .replace("T", "_");
-                date = Some(t);
-            }
-        }
-        if line.len() == 0 || &line[..1] == "#" {
-            continue;
-        }
-        let full_url = format!(
-            "{}{}",
-            util::url_prefix(&playlist.playlist_url, line).ok_or_else(o!())?,
-            line
-        );
-        // parse stream id
-        let id = line
-            .split("/")
-            .last()
-            .ok_or_else(o!())?
-            .split("_")
-            .collect::<Vec<_>>();
-        let id2 = id.get(2).ok_or_else(o!())?;
-        let id = id2.trim().parse::<u32>().map_err(e!())?;
-        // parse filenames
-        let date = date.as_ref().ok_or_else(o!())?;
-        let filename = format!("CB_{}_{}", playlist.username, date);
-        let stream = Info {
-            url: full_url,
-            filename,
-        };
-        streams.insert(id, stream);
-    }
-    Ok(streams)
+/// stream id of audio/video split segments ex. ".../seg_1_1234_video.mp4"
+fn split_segment_id(url: &str) -> Res<u64> {
+    let id = stream::Playlist::segment_name(url)
+        .split("_")
+        .collect::<Vec<_>>();
+    let id2 = id.get(2).ok_or_else(o!())?;
+    id2.trim().parse::<u64>().map_err(e!())
 }
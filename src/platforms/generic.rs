@@ -0,0 +1,122 @@
+use {
+    crate::{
+        config::Settings,
+        hls,
+        platforms::{Container, ModelOptions, Platform, Provider, Room, RoomState},
+        s,
+        stream::{Playlist, Stream},
+        util,
+    },
+    std::{collections::HashMap, sync::Arc, *},
+};
+type Res<T> = Result<T, crate::err::Error>;
+/// any master or media playlist, the model name is the playlist url
+pub struct Hls;
+impl Provider for Hls {
+    fn name(&self) -> &'static str {
+        "HLS"
+    }
+    fn referer(&self) -> &'static str {
+        ""
+    }
+    fn container(&self) -> Container {
+        Container::Probe
+    }
+    /// playlists of different sites share the platform
+    fn limit_per_host(&self) -> bool {
+        true
+    }
+    /// "<host>_<path>" of the url, without the playlist file
+    fn recording_name(&self, username: &str) -> String {
+        let url = username.split(['?', '#']).next().unwrap_or(username);
+        let url = url.split_once("://").map(|(_, u)| u).unwrap_or(url);
+        let path = url.rsplit_once('/').map(|(p, _)| p).unwrap_or(url);
+        path.chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' => c,
+                _ => '_',
+            })
+            .collect()
+    }
+    fn room_url(&self, username: &str) -> String {
+        username.to_string()
+    }
+    fn headers(&self, user_agent: &str) -> Res<HashMap<String, String>> {
+        util::create_headers(serde_json::json!({ "user-agent": user_agent })).map_err(s!())
+    }
+    fn get_playlist(
+        &self,
+        username: &str,
+        options: &ModelOptions,
+        settings: Arc<Settings>,
+    ) -> Res<Room> {
+        let headers = self.headers(&settings.user_agent).map_err(s!())?;
+        let playlist = match util::get_retry(&Platform(&Hls), username, 1, Some(&headers)) {
+            Ok(r) => r,
+            // the stream has not started or has ended
+            Err(e) if matches!(e.status(), Some(403 | 404 | 410)) => {
+                return Ok(Room::new(RoomState::Offline));
+            }
+            Err(e) => return Err(e).map_err(s!()),
+        };
+        if !playlist.contains("#EXT-X-STREAM-INF") {
+            if !playlist.contains("#EXTINF") {
+                return Ok(Room::new(RoomState::Offline));
+            }
+            return Ok(Room::public(Some(username.to_string()), None));
+        }
+        let master = hls::MasterPlaylist::parse(&playlist);
+        let preference = settings.variant_preference(&Platform(&Hls), options);
+        let variant = match master.select(&preference) {
+            Some(o) => o,
+            None => return Ok(Room::public(None, None)),
+        };
+        let playlist_audio_url = master
+            .audio_for(variant)
+            .and_then(|r| r.uri.as_deref())
+            .map(|uri| hls::resolve(username, uri));
+        Ok(Room::public(
+            Some(hls::resolve(username, &variant.uri)),
+            playlist_audio_url,
+        )
+        .variant(Some(variant)))
+    }
+    fn parse_playlist(&self, playlist: &mut Playlist) -> Res<Vec<Stream>> {
+        let video = playlist.media(false).map_err(s!())?;
+        let filename = playlist.filename(&video);
+        let audio = match playlist.playlist_audio_url {
+            Some(_) => Some(playlist.media(true).map_err(s!())?),
+            None => None,
+        };
+        // without a media sequence the index restarts on every poll, number segments by uri
+        playlist.segment_ids.retain(&video);
+        let mut streams = Vec::new();
+        for (i, segment) in video.segments.iter().enumerate() {
+            let id = match segment.sequence {
+                Some(sequence) => sequence,
+                None => playlist.segment_ids.id(segment),
+            };
+            let segment_audio = match audio.as_ref() {
+                // renditions share media sequence numbers, else the newest segments line up
+                Some(audio) => {
+                    let offset = audio.segments.len() as i64 - video.segments.len() as i64;
+                    let found = match segment.sequence {
+                        Some(sequence) => {
+                            audio.segments.iter().find(|s| s.sequence == Some(sequence))
+                        }
+                        None => usize::try_from(i as i64 + offset)
+                            .ok()
+                            .and_then(|n| audio.segments.get(n)),
+                    };
+                    match found {
+                        Some(found) => Some(found),
+                        None => continue,
+                    }
+                }
+                None => None,
+            };
+            streams.push(Stream::new(playlist, &filename, id, segment, segment_audio));
+        }
+        Ok(streams)
+    }
+}
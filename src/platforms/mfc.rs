@@ -1,15 +1,42 @@
 use {
-    crate::{config::Settings, e, o, platforms::Platform, s, stream, util},
+    crate::{
+        config::Settings,
+        e,
+        err::Error,
+        hls, o,
+        platforms::{Container, ModelOptions, Platform, Provider, Room, RoomState},
+        s, stream, util,
+    },
     std::{sync::Arc, *},
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
-pub fn get_playlist(
-    username: &str,
-    settings: Arc<Settings>,
-) -> Res<(Option<String>, Option<String>)> {
+type Res<T> = Result<T, crate::err::Error>;
+pub struct Mfc;
+impl Provider for Mfc {
+    fn name(&self) -> &'static str {
+        "MFC"
+    }
+    fn referer(&self) -> &'static str {
+        "https://www.myfreecams.com/"
+    }
+    fn container(&self) -> Container {
+        Container::MpegTs
+    }
+    fn get_playlist(
+        &self,
+        username: &str,
+        options: &ModelOptions,
+        settings: Arc<Settings>,
+    ) -> Res<Room> {
+        get_playlist(username, options, settings)
+    }
+    fn parse_playlist(&self, playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+        parse_playlist(playlist)
+    }
+}
+fn get_playlist(username: &str, options: &ModelOptions, settings: Arc<Settings>) -> Res<Room> {
     let headers = util::create_headers(serde_json::json!({
         "user-agent": &settings.user_agent,
-        "referer": format!("{}{}",Platform::MFC.referer(),username),
+        "referer": format!("{}{}",Platform(&Mfc).referer(),username),
 
     }))
     .map_err(s!())?;
@@ -17,7 +44,7 @@ pub fn get_playlist(
         "https://api-edge.myfreecams.com/usernameLookup/{}",
         username
     );
-    let json_raw = util::get_retry(&url, 5, Some(&headers)).map_err(s!())?;
+    let json_raw = util::get_retry(&Platform(&Mfc), &url, 5, Some(&headers)).map_err(s!())?;
     let json: serde_json::Value = serde_json::from_str(&json_raw).map_err(e!())?;
     let user = json
         .get("result")
@@ -27,15 +54,22 @@ pub fn get_playlist(
     let id = match user.get("id").ok_or_else(o!())?.as_i64() {
         Some(o) => o,
         None => {
-            return Err("user not found").map_err(s!())?;
+            return Err(Error::NotFound(username.to_string())).map_err(s!());
         }
     };
     let sessions = match user.get("sessions").ok_or_else(o!())?.as_array() {
         Some(o) => o,
-        None => return Ok((None, None)),
+        None => return Ok(Room::new(RoomState::Offline)),
     };
     if sessions.len() == 0 {
-        return Ok((None, None));
+        return Ok(Room::new(RoomState::Offline));
+    }
+    // video state of the session
+    match sessions[0].get("vs").and_then(|v| v.as_i64()) {
+        Some(2) => return Ok(Room::new(RoomState::Away)),
+        Some(12..=14) => return Ok(Room::new(RoomState::Private)),
+        Some(127) => return Ok(Room::new(RoomState::Offline)),
+        _ => (),
     }
     let server_name = sessions[0]
         .get("server_name")
@@ -43,7 +77,7 @@ pub fn get_playlist(
         .as_str()
         .ok_or_else(o!())?;
     if server_name.len() == 0 {
-        return Ok((None, None));
+        return Ok(Room::new(RoomState::Offline));
     }
     let phase = sessions[0]
         .get("phase")
@@ -60,49 +94,35 @@ pub fn get_playlist(
         "https://edgevideo.myfreecams.com/llhls/NxServer/{}/ngrp:mfc_{}{}{}.f4v_cmaf/playlist_sfm4s.m3u8",
         server_name, phase, playform_id, id
     );
-    let playlist = util::get_retry(&playlist_url, 1, Some(&headers)).map_err(s!())?;
-    for line in playlist.lines() {
-        if line.len() < 5 || &line[..1] == "#" {
-            continue;
-        }
-        let playlist_link = format!(
-            "{}/{}",
-            util::url_prefix(&playlist_url, line).ok_or_else(o!())?,
-            line
-        );
-        return Ok((Some(playlist_link), None));
-    }
-    return Ok((None, None));
+    let playlist =
+        util::get_retry(&Platform(&Mfc), &playlist_url, 1, Some(&headers)).map_err(s!())?;
+    let preference = settings.variant_preference(&Platform(&Mfc), options);
+    let master = hls::MasterPlaylist::parse(&playlist);
+    let variant = match master.select(&preference) {
+        Some(o) => o,
+        None => return Ok(Room::public(None, None)),
+    };
+    let playlist_link = format!(
+        "{}/{}",
+        util::url_prefix(&playlist_url, &variant.uri).ok_or_else(o!())?,
+        variant.uri
+    );
+    Ok(Room::public(Some(playlist_link), None)
+        .variant(Some(variant))
+        .model_id(id))
 }
-pub fn parse_playlist(playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+fn parse_playlist(playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+    let media = playlist.media(false).map_err(s!())?;
+    //parse filenames
+    let filename = playlist.filename(&media);
     let mut streams = Vec::new();
-    for line in playlist.playlist.as_ref().ok_or_else(o!())?.lines() {
-        if line.len() == 0 || &line[..1] == "#" {
-            continue;
-        }
-        // parse relevant information
-        let url = format!(
-            "{}/{}",
-            util::url_prefix(&playlist.playlist_url, line).ok_or_else(o!())?,
-            line
-        );
+    for segment in &media.segments {
         // parse stream id
-        let id_split = line.split(".").collect::<Vec<&str>>();
+        let name = stream::Playlist::segment_name(&segment.uri);
+        let id_split = name.split(".").collect::<Vec<&str>>();
         let id_raw = *id_split.get(1).ok_or_else(o!())?;
-        let id = util::remove_non_num(id_raw).parse::<u32>().map_err(e!())?;
-        //parse filenames
-        let date = util::date();
-        let filename = format!("MFC_{}_{}", playlist.username, date);
-        streams.push(stream::Stream::new(
-            &filename,
-            &url,
-            None,
-            id,
-            Platform::MFC,
-            playlist.settings.user_agent.clone(),
-            None,
-            None,
-        ));
+        let id = util::remove_non_num(id_raw).parse::<u64>().map_err(e!())?;
+        streams.push(stream::Stream::new(playlist, &filename, id, segment, None));
     }
     Ok(streams)
 }
@@ -1,5 +1,6 @@
 pub mod bonga;
 pub mod cb;
+pub mod generic;
 pub mod mfc;
 pub mod sc;
 pub mod scvr;
@@ -8,93 +9,344 @@ pub mod soda;
 use {
     crate::{
         config::Settings,
-        h, o, s,
-        stream::{Playlist, Stream},
+        debug,
+        err::Error,
+        error, h, history, hls, info, journal, log,
+        muxer::OutputFormat,
+        o, s, status,
+        stream::{Playlist, Stream, Target},
+        util, warn,
     },
     std::{
+        collections::HashMap,
+        path::PathBuf,
         sync::{Arc, RwLock},
         thread::JoinHandle,
+        time::{Duration, Instant},
         *,
     },
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
-pub enum Platform {
-    CB,
-    SC,
-    SCVR,
-    MFC,
-    BONGA,
-    SODA,
+type Res<T> = Result<T, crate::err::Error>;
+/// longest time a model is not polled, for geo-blocked models and as the not found backoff limit
+const SKIP: Duration = Duration::from_secs(24 * 60 * 60);
+/// first wait for a model that cannot be found, doubled each time it is still not found
+const NOT_FOUND_BACKOFF: Duration = Duration::from_secs(60 * 60);
+/// shortest interval when polling more often around a model's usual start time
+const MIN_INTERVAL: Duration = Duration::from_secs(10);
+/// state of a model's room as reported by its platform
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum RoomState {
+    Offline,
+    Public,
+    /// private, group or password protected show
+    Private,
+    Away,
+    Hidden,
+    Banned,
+    NotFound,
 }
-impl Platform {
-    pub fn list() -> Vec<Platform> {
-        use Platform::*;
-        vec![CB, SC, SCVR, MFC, BONGA, SODA]
+impl fmt::Display for RoomState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RoomState::*;
+        let name = match self {
+            Offline => "offline",
+            Public => "public",
+            Private => "private",
+            Away => "away",
+            Hidden => "hidden",
+            Banned => "banned",
+            NotFound => "not found",
+        };
+        write!(f, "{}", name)
     }
-    pub fn new(key: &str) -> Option<Self> {
-        use Platform::*;
-        match key {
-            "CB" => Some(CB),
-            "MFC" => Some(MFC),
-            "SC" => Some(SC),
-            "SCVR" => Some(SCVR),
-            "BONGA" => Some(BONGA),
-            "SODA" => Some(SODA),
-            _ => None,
-        }
-    }
-    pub fn parse_playlist(&self) -> fn(&mut Playlist) -> Res<Vec<Stream>> {
-        use Platform::*;
-        match self {
-            CB => cb::parse_playlist,
-            MFC => mfc::parse_playlist,
-            SC => sc::parse_playlist,
-            SCVR => scvr::parse_playlist,
-            BONGA => bonga::parse_playlist,
-            SODA => soda::parse_playlist,
+}
+/// room of a model, the playlists are set when it can be recorded
+pub struct Room {
+    pub state: RoomState,
+    pub playlist_url: Option<String>,
+    pub playlist_audio_url: Option<String>,
+    pub info: StreamInfo,
+}
+impl Room {
+    /// room without a stream to record
+    pub fn new(state: RoomState) -> Self {
+        Self {
+            state,
+            playlist_url: None,
+            playlist_audio_url: None,
+            info: StreamInfo::default(),
         }
     }
-    fn get_playlist(&self) -> fn(&str, Arc<Settings>) -> Res<(Option<String>, Option<String>)> {
-        use Platform::*;
+    pub fn public(playlist_url: Option<String>, playlist_audio_url: Option<String>) -> Self {
+        Self {
+            state: RoomState::Public,
+            playlist_url,
+            playlist_audio_url,
+            info: StreamInfo::default(),
+        }
+    }
+    /// notes the variant being recorded
+    pub fn variant(mut self, variant: Option<&hls::Variant>) -> Self {
+        if let Some(variant) = variant {
+            self.info.resolution = variant.resolution.map(|(w, h)| format!("{}x{}", w, h));
+            self.info.bandwidth = variant.bandwidth;
+        }
+        self
+    }
+    /// notes the id of the model on the platform
+    pub fn model_id(mut self, model_id: impl ToString) -> Self {
+        self.info.model_id = Some(model_id.to_string());
+        self
+    }
+}
+/// details of a stream kept with its recording
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct StreamInfo {
+    /// id of the model on the platform
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_id: Option<String>,
+    /// "1280x720" of the variant
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<String>,
+    /// bits per second of the variant
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<u64>,
+}
+/// container of a platform's segments
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Container {
+    MpegTs,
+    Mp4,
+    /// either, found from the recording by the muxer
+    Probe,
+}
+impl Container {
+    /// ffmpeg input format
+    pub fn format(&self) -> &'static str {
         match self {
-            CB => cb::get_playlist,
-            MFC => mfc::get_playlist,
-            SC => sc::get_playlist,
-            SCVR => scvr::get_playlist,
-            BONGA => bonga::get_playlist,
-            SODA => soda::get_playlist,
+            Container::MpegTs => "mpegts",
+            Container::Mp4 | Container::Probe => "mp4",
         }
     }
-    pub fn referer(&self) -> &'static str {
-        use Platform::*;
+    /// extension of recordings saved without remuxing
+    pub fn extension(&self) -> &'static str {
         match self {
-            CB => "https://chaturbate.com/",
-            MFC => "https://www.myfreecams.com/",
-            SC => "https://stripchat.com/",
-            SCVR => "https://vr.stripchat.com/",
-            BONGA => "https://bongacams.com/",
-            SODA => "https://www.camsoda.com/",
+            Container::MpegTs => "ts",
+            Container::Mp4 | Container::Probe => "mp4",
+        }
+    }
+}
+/// a site models are recorded from, implemented once per platform module
+pub trait Provider: Sync {
+    /// key in the config file, ex. "CB"
+    fn name(&self) -> &'static str;
+    fn referer(&self) -> &'static str;
+    /// start of recording filenames, "<prefix>_<username>_<date>"
+    fn filename_prefix(&self) -> &'static str {
+        self.name()
+    }
+    /// folder and filename part for a model, the username unless it is not a valid filename
+    fn recording_name(&self, username: &str) -> String {
+        username.to_string()
+    }
+    /// page of the model's room, written to recordings as their source
+    fn room_url(&self, username: &str) -> String {
+        format!("{}{}", self.referer(), username)
+    }
+    /// container of the segments, streams with separate audio are always mp4
+    fn container(&self) -> Container;
+    /// headers for playlist and segment requests
+    fn headers(&self, user_agent: &str) -> Res<HashMap<String, String>> {
+        util::create_headers(serde_json::json!({
+            "user-agent": user_agent,
+            "referer": self.referer(),
+        }))
+        .map_err(s!())
+    }
+    /// looks up the room of a model, with its playlists if it can be recorded
+    fn get_playlist(
+        &self,
+        username: &str,
+        options: &ModelOptions,
+        settings: Arc<Settings>,
+    ) -> Res<Room>;
+    /// state of every room that is not offline, from one listing of the platform
+    /// None if the platform cannot list rooms, its models are then polled one by one
+    fn rooms(&self, _settings: &Settings) -> Option<Res<HashMap<String, RoomState>>> {
+        None
+    }
+    /// streams of the downloaded playlist
+    fn parse_playlist(&self, playlist: &mut Playlist) -> Res<Vec<Stream>>;
+    /// requests are limited per host instead of for the whole platform
+    fn limit_per_host(&self) -> bool {
+        false
+    }
+    /// pause after polling a model, before the next model of the platform
+    fn poll_delay(&self) -> Duration {
+        Duration::from_millis(500)
+    }
+}
+/// every supported platform, new platforms are added here
+static PROVIDERS: &[&dyn Provider] = &[
+    &cb::Cb,
+    &sc::Sc,
+    &scvr::Scvr,
+    &mfc::Mfc,
+    &bonga::Bonga,
+    &soda::Soda,
+    &generic::Hls,
+];
+/// a registered platform, compared and serialized by name
+#[derive(Clone)]
+pub struct Platform(&'static dyn Provider);
+impl Platform {
+    pub fn list() -> Vec<Platform> {
+        PROVIDERS.iter().map(|p| Platform(*p)).collect()
+    }
+    pub fn new(key: &str) -> Option<Self> {
+        PROVIDERS
+            .iter()
+            .find(|p| p.name() == key)
+            .map(|p| Platform(*p))
+    }
+    /// the provider, borrowed for longer than the platform
+    pub fn provider(&self) -> &'static dyn Provider {
+        self.0
+    }
+}
+impl ops::Deref for Platform {
+    type Target = dyn Provider;
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+impl fmt::Debug for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+impl PartialEq for Platform {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+impl Eq for Platform {}
+impl hash::Hash for Platform {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.name().hash(state);
+    }
+}
+impl serde::Serialize for Platform {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.name())
+    }
+}
+impl<'de> serde::Deserialize<'de> for Platform {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let key = String::deserialize(deserializer)?;
+        Platform::new(&key)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown platform {}", key)))
+    }
+}
+/// per model recording options, set in the config file
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ModelOptions {
+    /// maximum resolution to record ex. "720p"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+    /// output root, recordings are saved to "<output>/<username>/"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    pub enabled: bool,
+    /// maximum length of a session, the model is not recorded again until it goes offline
+    #[serde(
+        alias = "max_duration",
+        with = "crate::util::serde_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_duration: Option<Duration>,
+    /// time between checks, overrides the platform's poll interval
+    #[serde(
+        with = "crate::util::serde_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub poll_interval: Option<Duration>,
+    /// length a recording is split into parts at, overrides the platform's
+    #[serde(
+        with = "crate::util::serde_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub split_duration: Option<Duration>,
+    /// size a recording is split into parts at, overrides the platform's
+    #[serde(
+        with = "crate::util::serde_option_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub split_size: Option<u64>,
+    /// container of the model's recordings, overrides the platform's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_format: Option<OutputFormat>,
+    /// ffmpeg profile run on the model's recordings, overrides the platform's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+impl Default for ModelOptions {
+    fn default() -> Self {
+        Self {
+            quality: None,
+            output: None,
+            enabled: true,
+            max_duration: None,
+            poll_interval: None,
+            split_duration: None,
+            split_size: None,
+            output_format: None,
+            profile: None,
         }
     }
 }
 pub struct Model {
     pub platform: Platform,
     pub username: String,
+    pub options: ModelOptions,
+    /// start of the current online session, reset when the model goes offline
+    session_start: Option<Instant>,
+    room: Option<RoomState>,
+    /// not polled before this
+    next_check: Option<Instant>,
+    /// times in a row the room was not found or banned
+    not_found: u32,
     downloading: Arc<RwLock<bool>>,
     playlist_link: Option<String>,
     playlist_audio_link: Option<String>,
-    thread_handles: Vec<JoinHandle<Result<(), String>>>,
+    /// details of the stream found with the playlist links
+    stream_info: StreamInfo,
+    thread_handles: Vec<JoinHandle<Res<()>>>,
     abort: Arc<RwLock<bool>>,
 }
 impl Model {
-    pub fn new(platform: Platform, username: &str) -> Self {
+    pub fn new(platform: Platform, username: &str, options: ModelOptions) -> Self {
         Self {
             platform,
             username: username.to_string(),
+            options,
+            session_start: None,
+            room: None,
+            next_check: None,
+            not_found: 0,
             downloading: Arc::new(RwLock::new(false)),
             playlist_link: None,
             playlist_audio_link: None,
+            stream_info: StreamInfo::default(),
             thread_handles: Vec::new(),
             abort: Arc::new(RwLock::new(false)),
         }
@@ -102,24 +354,99 @@ impl Model {
     pub fn composite_key(&self) -> String {
         format!("{:?}:{}", self.platform, self.username)
     }
+    /// polls the room, the playlist links are set if it can be recorded
     fn is_online(&mut self, settings: Arc<Settings>) -> bool {
-        let (playlist_link, playlist_audio_link) =
-            match self.platform.get_playlist()(&self.username, settings) {
-                Ok(r) => r,
-                Err(e) => {
-                    eprintln!("{}", e);
-                    (None, None)
-                }
-            };
-        self.playlist_audio_link = playlist_audio_link;
-        self.playlist_link = playlist_link;
+        self.playlist_link = None;
+        self.playlist_audio_link = None;
+        let room = match self
+            .platform
+            .get_playlist(&self.username, &self.options, settings)
+        {
+            Ok(r) => r,
+            Err(e) if matches!(e.root(), Error::NotFound(_)) => {
+                debug!("{}", e);
+                Room::new(RoomState::NotFound)
+            }
+            // logged once when the requests were paused
+            Err(e) if matches!(e.root(), Error::Paused(_)) => {
+                debug!("{}", e);
+                return false;
+            }
+            Err(e) if matches!(e.root(), Error::GeoBlocked) => {
+                warn!("{}, checking again in {}", e, util::format_duration(SKIP));
+                self.next_check = Some(Instant::now() + SKIP);
+                return false;
+            }
+            Err(e) => {
+                error!("{}", e);
+                return false;
+            }
+        };
+        self.set_room(room.state);
+        self.playlist_audio_link = room.playlist_audio_url;
+        self.stream_info = room.info;
+        self.playlist_link = room.playlist_url;
         self.playlist_link.is_some()
     }
-    fn is_downloading(&self) -> Res<bool> {
+    /// logs changes of the room state
+    fn set_room(&mut self, state: RoomState) {
+        if self.room != Some(state) {
+            info!("room is {}", state);
+            self.room = Some(state);
+            let _ = status::set_room(&self.composite_key(), state);
+        }
+    }
+    /// whether the model is due to be polled
+    pub fn is_due(&self) -> bool {
+        self.next_check.is_none_or(|t| Instant::now() >= t)
+    }
+    /// sets the next check from the poll interval, models that are gone are backed off
+    fn schedule(&mut self, settings: &Settings) {
+        let mut interval = settings.poll_interval(&self.platform, &self.options);
+        match self.room {
+            Some(state @ (RoomState::NotFound | RoomState::Banned)) => {
+                self.not_found += 1;
+                interval = NOT_FOUND_BACKOFF
+                    .saturating_mul(1 << (self.not_found - 1).min(5))
+                    .min(SKIP);
+                warn!(
+                    "room is {}, checking again in {}",
+                    state,
+                    util::format_duration(interval)
+                );
+            }
+            _ => {
+                self.not_found = 0;
+                // polled more often around the times it usually goes live
+                if history::is_usual_time(&self.composite_key()) {
+                    interval = (interval / 4).max(MIN_INTERVAL.min(interval));
+                }
+                let jitter = settings.poll_jitter(&self.platform);
+                interval = interval.mul_f64(1.0 + jitter * (2.0 * util::random() - 1.0));
+            }
+        }
+        let next = Instant::now() + interval;
+        self.next_check = Some(self.next_check.map_or(next, |t| t.max(next)));
+    }
+    /// checks the room without recording
+    pub fn check(&mut self, settings: Arc<Settings>) -> Option<Room> {
+        self.is_online(settings);
+        Some(Room {
+            state: self.room?,
+            playlist_url: self.playlist_link.clone(),
+            playlist_audio_url: self.playlist_audio_link.clone(),
+            info: self.stream_info.clone(),
+        })
+    }
+    /// waits for the current recording to finish
+    pub fn wait(&mut self) {
+        self.join_handles_drop()
+    }
+    pub fn is_downloading(&self) -> Res<bool> {
         Ok(*self.downloading.read().map_err(s!())?)
     }
     fn join_handles_drop(&mut self) {
-        let mut errors: Vec<String> = Vec::new();
+        let mut errors: Vec<Error> = Vec::new();
         for handle in self.thread_handles.drain(..) {
             handle
                 .join()
@@ -128,12 +455,12 @@ impl Model {
                 .unwrap_or_else(|e| errors.push(e));
         }
         for e in errors {
-            eprintln!("{}", e)
+            error!("{}", e)
         }
     }
     fn join_finished_handles(&mut self) -> Res<()> {
-        let mut errors = Vec::<String>::new();
-        let handles: Vec<JoinHandle<Result<(), String>>> = self.thread_handles.drain(..).collect();
+        let mut errors = Vec::<Error>::new();
+        let handles: Vec<JoinHandle<Res<()>>> = self.thread_handles.drain(..).collect();
         for handle in handles {
             if handle.is_finished() {
                 handle
@@ -144,44 +471,117 @@ impl Model {
             } else {
                 self.thread_handles.push(handle);
             }
-            if errors.len() > 0 {
-                return Err(errors.join("\n")).map_err(s!())?;
+            if let Some(e) = errors.pop() {
+                return Err(e).map_err(s!())?;
             }
         }
         return Ok(());
     }
-    /// main function for downloading a model
-    pub fn download(&mut self, settings: Arc<Settings>) -> Res<()> {
+    /// main function for downloading a model, returns whether the platform was requested
+    /// listed is the room state from a listing of the platform, the room is only polled if it is public
+    pub fn download(&mut self, settings: Arc<Settings>, listed: Option<RoomState>) -> Res<bool> {
+        let _scope = log::scope(&self.platform, &self.username);
         self.join_finished_handles().map_err(s!())?;
+        let key = self.composite_key();
+        status::track(
+            &self.platform,
+            &self.username,
+            self.options.enabled,
+            &self.abort,
+        )
+        .map_err(s!())?;
         if self.is_downloading().map_err(s!())? {
-            return Ok(());
+            // room changes during a recording are marked as chapters
+            if let Some(state) = listed {
+                self.set_room(state);
+            }
+            if !self.is_due() {
+                return Ok(false);
+            }
+            // platforms without a listing have their room polled
+            let polled = listed.is_none();
+            if polled {
+                self.is_online(settings.clone());
+            }
+            // not due again until the next interval, so the listing is not fetched every loop
+            self.schedule(&settings);
+            return Ok(polled);
         }
-        if self.is_online(settings.clone()) {
-            self.start_download_thread(settings).map_err(s!())?;
+        if !self.options.enabled || !self.is_due() {
+            return Ok(false);
         }
-        Ok(())
+        let (online, polled) = match listed {
+            Some(state) if state != RoomState::Public => {
+                self.playlist_link = None;
+                self.playlist_audio_link = None;
+                self.set_room(state);
+                (false, false)
+            }
+            _ => (self.is_online(settings.clone()), true),
+        };
+        self.schedule(&settings);
+        if !online {
+            status::set_state(&key, status::State::Offline).map_err(s!())?;
+            // a private show or time away continues the session
+            if self.room == Some(RoomState::Offline) {
+                self.session_start = None;
+                status::clear_stopped(&key).map_err(s!())?;
+            }
+            return Ok(polled);
+        }
+        status::set_state(&key, status::State::Online).map_err(s!())?;
+        // stopped through the api, waits for the model to go offline
+        if status::is_stopped(&key) {
+            return Ok(polled);
+        }
+        if self.session_start.is_none()
+            && let Err(e) = history::went_live(&key)
+        {
+            error!("{}", e);
+        }
+        let session_start = *self.session_start.get_or_insert_with(Instant::now);
+        let mut options = self.options.clone();
+        if let Some(max_duration) = self.options.max_duration {
+            // only record the remainder of the session
+            match max_duration.checked_sub(session_start.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => options.max_duration = Some(remaining),
+                _ => return Ok(polled),
+            }
+        }
+        self.start_download_thread(options, settings)
+            .map_err(s!())?;
+        Ok(polled)
     }
-    fn start_download_thread(&mut self, settings: Arc<Settings>) -> Res<()> {
+    fn start_download_thread(&mut self, options: ModelOptions, settings: Arc<Settings>) -> Res<()> {
         let username = self.username.clone();
         let abort = self.abort.clone();
+        *abort.write().map_err(s!())? = false;
         let playlist_url = self.playlist_link.clone().ok_or_else(o!())?;
         let playlist_audio_url = self.playlist_audio_link.clone();
+        let stream_info = self.stream_info.clone();
         let platform = self.platform.clone();
         let settings = settings.clone();
         let downloading = self.downloading.clone();
         *downloading.write().map_err(s!())? = true;
+        let resume = journal::take(&platform, &username);
+        let context = log::context();
         let handle = thread::spawn(move || {
-            Playlist::new(
+            let _scope = log::enter(context);
+            let target = Target {
                 platform,
                 username,
+                options,
+            };
+            let mut playlist = Playlist::new(
+                target,
                 playlist_url,
                 playlist_audio_url,
                 abort,
                 downloading,
                 settings,
-            )
-            .playlist()
-            .map_err(s!())
+            );
+            playlist.info = stream_info;
+            playlist.playlist(resume).map_err(s!())
         });
         self.thread_handles.push(handle);
         Ok(())
@@ -190,6 +590,16 @@ impl Model {
         *self.abort.write().map_err(s!())? = true;
         Ok(())
     }
+    /// applies changed options, stops the current recording if the model was disabled
+    pub fn set_options(&mut self, options: ModelOptions) -> Res<()> {
+        if !options.enabled && self.is_downloading().map_err(s!())? {
+            self.abort().map_err(s!())?;
+        }
+        self.options = options;
+        // checked again with the new poll interval
+        self.next_check = None;
+        Ok(())
+    }
 }
 impl hash::Hash for Model {
     fn hash<H: hash::Hasher>(&self, state: &mut H) {
@@ -205,7 +615,7 @@ impl PartialEq for Model {
 }
 impl Clone for Model {
     fn clone(&self) -> Self {
-        Self::new(self.platform.clone(), &self.username)
+        Self::new(self.platform.clone(), &self.username, self.options.clone())
     }
 }
 impl Drop for Model {
@@ -218,6 +628,50 @@ impl serde::Serialize for Model {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_str(&self.username.clone())
+        if self.options == ModelOptions::default() {
+            return serializer.serialize_str(&self.username.clone());
+        }
+        #[derive(serde::Serialize)]
+        struct Entry<'a> {
+            name: &'a str,
+            #[serde(flatten)]
+            options: &'a ModelOptions,
+        }
+        Entry {
+            name: &self.username,
+            options: &self.options,
+        }
+        .serialize(serializer)
+    }
+}
+/// model entry in the config file, either a username or an object with options
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum ModelEntry {
+    Name(String),
+    Options {
+        name: String,
+        #[serde(flatten)]
+        options: ModelOptions,
+        /// keys that are not options, ex. misspelled
+        #[serde(flatten)]
+        unknown: HashMap<String, serde_json::Value>,
+    },
+}
+impl ModelEntry {
+    pub fn into_model(self, platform: Platform) -> Model {
+        match self {
+            ModelEntry::Name(name) => Model::new(platform, &name, ModelOptions::default()),
+            ModelEntry::Options {
+                name,
+                options,
+                unknown,
+            } => {
+                for key in unknown.keys() {
+                    warn!("unknown option {} of {}", key, name);
+                }
+                Model::new(platform, &name, options)
+            }
+        }
     }
 }
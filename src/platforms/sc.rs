@@ -1,88 +1,161 @@
 use {
     crate::{
         config::Settings,
-        debug_eprintln, e, o,
-        platforms::Platform,
+        debug, e,
+        err::Error,
+        hls, o,
+        platforms::{Container, ModelOptions, Platform, Provider, Room, RoomState, scvr},
         s, stream,
         util::{self},
     },
     std::{
         collections::HashMap,
-        sync::{Arc, OnceLock},
+        sync::{Arc, Mutex, OnceLock},
+        time::{Duration, Instant},
         *,
     },
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
-#[inline]
-pub fn get_playlist(
-    username: &str,
-    settings: Arc<Settings>,
-) -> Res<(Option<String>, Option<String>)> {
-    sc_get_playlist(username, false, settings)
+type Res<T> = Result<T, crate::err::Error>;
+pub struct Sc;
+impl Provider for Sc {
+    fn name(&self) -> &'static str {
+        "SC"
+    }
+    fn referer(&self) -> &'static str {
+        "https://stripchat.com/"
+    }
+    fn container(&self) -> Container {
+        Container::Mp4
+    }
+    fn get_playlist(
+        &self,
+        username: &str,
+        options: &ModelOptions,
+        settings: Arc<Settings>,
+    ) -> Res<Room> {
+        sc_get_playlist(username, false, options, settings)
+    }
+    fn rooms(&self, settings: &Settings) -> Option<Res<HashMap<String, RoomState>>> {
+        Some(online_rooms(settings))
+    }
+    fn parse_playlist(&self, playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+        sc_parse_playlist(playlist)
+    }
+}
+/// primary tags of the model listing, a listing only has models of one
+const PRIMARY_TAGS: [&str; 4] = ["girls", "couples", "men", "trans"];
+/// models listed by the front api a page at a time, the listing also refreshes the hls prefix
+fn online_rooms(settings: &Settings) -> Res<HashMap<String, RoomState>> {
+    const LIMIT: usize = 500;
+    let platform = Platform(&Sc);
+    let headers = platform.headers(&settings.user_agent).map_err(s!())?;
+    let mut rooms = HashMap::new();
+    for tag in PRIMARY_TAGS {
+        let mut offset = 0;
+        loop {
+            let url = format!(
+                "https://stripchat.com/api/front/models?primaryTag={}&limit={}&offset={}",
+                tag, LIMIT, offset
+            );
+            let json_raw = util::get_retry(&platform, &url, 2, Some(&headers)).map_err(s!())?;
+            let json: serde_json::Value = serde_json::from_str(&json_raw).map_err(e!())?;
+            if let Some(prefix) = listed_hls_prefix(&json) {
+                *HLS_PREFIX.lock().map_err(s!())? = Some((prefix, Instant::now()));
+            }
+            let models = json
+                .get("models")
+                .and_then(|m| m.as_array())
+                .ok_or_else(o!())?;
+            let listed = rooms.len();
+            for model in models {
+                let username = model.get("username").and_then(|u| u.as_str());
+                let status = model.get("status").and_then(|s| s.as_str());
+                if let (Some(username), Some(status)) = (username, status) {
+                    let state = room_state(status).unwrap_or(RoomState::Public);
+                    rooms.insert(username.to_lowercase(), state);
+                }
+            }
+            // pages can be shorter than the limit, the listing ends with a page of no new models
+            if rooms.len() == listed {
+                break;
+            }
+            offset += models.len();
+        }
+    }
+    Ok(rooms)
 }
-#[inline]
-pub fn parse_playlist(playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
-    sc_parse_playlist(playlist, false)
+/// state of a model status that is not public
+fn room_state(status: &str) -> Option<RoomState> {
+    match status {
+        "off" => Some(RoomState::Offline),
+        "idle" => Some(RoomState::Away),
+        "private" | "p2p" | "groupShow" | "virtualPrivate" => Some(RoomState::Private),
+        _ => None,
+    }
 }
 pub fn sc_get_playlist(
     username: &str,
     vr: bool,
+    options: &ModelOptions,
     settings: Arc<Settings>,
-) -> Res<(Option<String>, Option<String>)> {
-    let platform = if vr { Platform::SCVR } else { Platform::SC };
+) -> Res<Room> {
+    let platform = if vr {
+        Platform(&scvr::Scvr)
+    } else {
+        Platform(&Sc)
+    };
     let headers = util::create_headers(serde_json::json!({
         "user-agent": &settings.user_agent,
         "referer": format!("{}{}",platform.referer(),username),
 
     }))
     .map_err(s!())?;
-    // get hls url prefix
-    let url = "https://stripchat.com/api/front/models?primaryTag=girls";
-    let json_raw = util::get_retry(url, 5, Some(&headers)).map_err(s!())?;
-    let json: serde_json::Value = serde_json::from_str(&json_raw).map_err(e!())?;
-    let ref_hls = json
-        .get("models")
-        .and_then(|o| o.as_array()?.get(0)?.get("hlsPlaylist")?.as_str())
-        .ok_or_else(o!())?;
-    let hls_prefix = ref_hls
-        .split("/")
-        .collect::<Vec<&str>>()
-        .get(..3)
-        .ok_or_else(o!())?
-        .join("/");
+    let hls_prefix = hls_prefix(&platform, &headers).map_err(s!())?;
     // get model ID
     let url = format!(
         "https://stripchat.com/api/front/v2/models/username/{}/cam",
         username
     );
-    let json_raw = util::get_retry(&url, 5, Some(&headers)).map_err(s!())?;
+    let json_raw = match util::get_retry(&platform, &url, 5, Some(&headers)) {
+        Ok(r) => r,
+        Err(e) if e.status() == Some(404) => {
+            return Err(Error::NotFound(username.to_string())).map_err(s!());
+        }
+        Err(e) => return Err(e).map_err(s!()),
+    };
     let json: serde_json::Value = serde_json::from_str(&json_raw).map_err(e!())?;
+    let user = json.get("user").and_then(|o| o.get("user"));
+    if user.and_then(|u| u.get("isDeleted")?.as_bool()) == Some(true) {
+        return Ok(Room::new(RoomState::NotFound));
+    }
+    if user.and_then(|u| u.get("isBlocked")?.as_bool()) == Some(true) {
+        return Ok(Room::new(RoomState::Banned));
+    }
+    if let Some(state) = user.and_then(|u| room_state(u.get("status")?.as_str()?)) {
+        return Ok(Room::new(state));
+    }
     let model_id = json
         .get("user")
         .and_then(|o| o.get("user")?.get("id")?.as_i64())
         .ok_or_else(o!())?;
-    // get largest HLS stream
+    // get master playlist
     let vr = if vr { "_vr" } else { "" };
     let playlist_url = format!(
         "{}/hls/{}{}/master/{}{}.m3u8",
         hls_prefix, model_id, vr, model_id, vr
     );
-    // below is the transoded streams, (maybe add resolution settings in future)
-    //let playlist_url = format!("{}/hls/{}_vr/master/{}_vr_auto.m3u8", hls_prefix, model_id, model_id);
-    let playlist = match util::get_retry(&playlist_url, 1, Some(&headers)).map_err(s!()) {
+    let playlist = match util::get_retry(&platform, &playlist_url, 1, Some(&headers)).map_err(s!())
+    {
         Ok(r) => r,
         Err(e) => {
-            debug_eprintln!("{}", e);
-            return Ok((None, None));
+            debug!("{}", e);
+            return Ok(Room::new(RoomState::Offline));
         }
     };
-    let mut playlist_url = None;
-    for line in playlist.lines() {
-        if line.len() < 5 || &line[..1] == "#" {
-            continue;
-        }
-        playlist_url = Some(line.to_string());
-    }
+    let preference = settings.variant_preference(&platform, options);
+    let master = hls::MasterPlaylist::parse(&playlist);
+    let variant = master.select(&preference);
+    let mut playlist_url = variant.map(|v| v.uri.clone());
     if playlist.contains("EXT-X-MOUFLON") {
         for line in playlist.lines() {
             if !line.contains("EXT-X-MOUFLON") {
@@ -101,94 +174,86 @@ pub fn sc_get_playlist(
             }
         }
     }
-    return Ok((playlist_url, None));
+    Ok(Room::public(playlist_url, None)
+        .variant(variant)
+        .model_id(model_id))
 }
 
-pub fn sc_parse_playlist(playlist: &mut stream::Playlist, vr: bool) -> Res<Vec<stream::Stream>> {
-    let platform = if vr { Platform::SCVR } else { Platform::SC };
-    let mut streams = Vec::new();
-    let mut date: Option<String> = None;
-    let mut key: Option<String> = None;
-    let enumerated_lines: Vec<(usize, &str)> = playlist
-        .playlist
-        .as_ref()
-        .ok_or_else(o!())?
-        .lines()
-        .enumerate()
-        .collect();
-    for (line_number, line) in &enumerated_lines {
-        // get m3u8 encryption key
-        if key.is_none() {
-            if line.contains("#EXT-X-MOUFLON:PSCH") {
-                let line_segments: Vec<&str> = line.split(":").collect();
-                let k = *(line_segments.get(3).ok_or_else(o!())?);
-                let v = psch(&playlist.settings.user_agent)
-                    .get(k)
-                    .ok_or_else(o!())?
-                    .clone();
-                key = Some(v);
-            }
-        }
-        // parse MP4 header
-        if playlist.mp4_header.is_none() {
-            if line.contains("EXT-X-MAP:URI") {
-                // parse header url
-                let header_url_split = line.split("\"").collect::<Vec<&str>>();
-                if header_url_split.len() < 2 {
-                    return Err("can not get mp4 header file".into());
-                }
-                let header_url = header_url_split[1];
-                let http_headers = util::create_headers(serde_json::json!({
-                    "user-agent": &playlist.settings.user_agent,
-                    "referer": platform.referer(),
-
-                }))
-                .map_err(s!())?;
-                let header =
-                    util::get_retry_vec(header_url, 5, Some(&http_headers)).map_err(s!())?;
-                playlist.mp4_header = Some(sync::Arc::new(header))
-            }
-        }
-        // parse date and time from playlist
-        if date.is_none() {
-            if let Some(n) = line.find("TIME") {
-                if line.len() < 21 {
-                    return Err("error parsing date from playlist")?;
-                }
-                let t = (&line.get(n + 7..n + 21).ok_or_else(o!())?)
-                    .replace(":", "-")
-                    .replace("T", "_");
-                date = Some(t);
-            }
+/// hls host shared by every model, with when it was fetched
+static HLS_PREFIX: Mutex<Option<(String, Instant)>> = Mutex::new(None);
+/// how long the hls host is reused before it is looked up again
+const HLS_PREFIX_AGE: Duration = Duration::from_secs(10 * 60);
+/// hls url prefix, found from the playlist of any online model, cached from the last listing
+fn hls_prefix(platform: &Platform, headers: &HashMap<String, String>) -> Res<String> {
+    let cached = HLS_PREFIX.lock().map_err(s!())?.clone();
+    if let Some((prefix, fetched)) = cached
+        && fetched.elapsed() < HLS_PREFIX_AGE
+    {
+        return Ok(prefix);
+    }
+    let url = "https://stripchat.com/api/front/models?primaryTag=girls";
+    let json_raw = util::get_retry(platform, url, 5, Some(headers)).map_err(s!())?;
+    let json: serde_json::Value = serde_json::from_str(&json_raw).map_err(e!())?;
+    let prefix = listed_hls_prefix(&json).ok_or_else(o!())?;
+    *HLS_PREFIX.lock().map_err(s!())? = Some((prefix.clone(), Instant::now()));
+    Ok(prefix)
+}
+/// scheme and host of the playlist of the first model in a listing
+fn listed_hls_prefix(json: &serde_json::Value) -> Option<String> {
+    let ref_hls = json
+        .get("models")?
+        .as_array()?
+        .first()?
+        .get("hlsPlaylist")?
+        .as_str()?;
+    Some(
+        ref_hls
+            .split("/")
+            .collect::<Vec<&str>>()
+            .get(..3)?
+            .join("/"),
+    )
+}
+pub fn sc_parse_playlist(playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+    let media = playlist.media(false).map_err(s!())?;
+    // get m3u8 encryption key
+    let key = match media
+        .tags
+        .iter()
+        .find(|t| t.contains("#EXT-X-MOUFLON:PSCH"))
+    {
+        Some(line) => {
+            let line_segments: Vec<&str> = line.split(":").collect();
+            let k = *(line_segments.get(3).ok_or_else(o!())?);
+            let v = psch(&playlist.settings.user_agent)
+                .get(k)
+                .ok_or_else(o!())?
+                .clone();
+            Some(v)
         }
-        if line.len() == 0 || &line[..1] == "#" {
-            continue;
+        None => None,
+    };
+    // parse filename
+    let filename = playlist.filename(&media);
+    let mut streams = Vec::new();
+    for segment in &media.segments {
+        // decrypt the segment url from the tag preceding it
+        let mut segment = segment.clone();
+        if key.is_some() {
+            let mouflon_line = segment
+                .tags
+                .iter()
+                .rev()
+                .find(|t| t.contains("MOUFLON"))
+                .or(segment.tags.last())
+                .ok_or_else(o!())?;
+            segment.uri = decrypt(key.as_deref(), mouflon_line).map_err(s!())?;
         }
-        // parse relevant information
-        let url = if key.is_none() {
-            line.to_string()
-        } else {
-            let (_, mouflon_line) = &enumerated_lines[line_number.saturating_sub(1)];
-            decrypt(key.as_deref(), *mouflon_line).map_err(s!())?
-        };
         // parse stream id
-        let id = url.split("_").last().ok_or_else(o!())?;
+        let id = segment.uri.split("_").last().ok_or_else(o!())?;
         let n = id.find(".").ok_or_else(o!())?;
-        let id = id[..n].trim().parse::<u32>().map_err(e!())?;
-        // parse filename
-        let vr_str = if vr { "SCVR" } else { "SC" };
-        let date = date.as_ref().ok_or_else(o!())?;
-        let filename = format!("{}_{}_{}", vr_str, playlist.username, date);
-        streams.push(stream::Stream::new(
-            &filename,
-            &url,
-            None,
-            id,
-            platform.clone(),
-            playlist.settings.user_agent.clone(),
-            playlist.mp4_header.clone(),
-            None,
-        ));
+        let id = id[..n].trim().parse::<u64>().map_err(e!())?;
+        streams.push(stream::Stream::new(playlist, &filename, id, &segment, None));
     }
     Ok(streams)
 }
@@ -215,6 +280,7 @@ fn psch(useragent: &str) -> Arc<HashMap<String, String>> {
         "user-agent": useragent,
     })).unwrap_or_default();
     let map = util::get_retry(
+        &Platform(&Sc),
         "https://raw.githubusercontent.com/kesamom/stripchat_mouflon/refs/heads/main/stripchat_mouflon_keys.json",
         1,
         Some(&headers)
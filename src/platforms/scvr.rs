@@ -1,20 +1,32 @@
 use {
     crate::{
         config::Settings,
-        platforms::sc,
+        platforms::{Container, ModelOptions, Provider, Room, sc},
         stream::{Playlist, Stream},
     },
     std::{sync::Arc, *},
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
-#[inline]
-pub fn get_playlist(
-    username: &str,
-    settings: Arc<Settings>,
-) -> Res<(Option<String>, Option<String>)> {
-    sc::sc_get_playlist(username, true, settings)
-}
-#[inline]
-pub fn parse_playlist(playlist: &mut Playlist) -> Res<Vec<Stream>> {
-    sc::sc_parse_playlist(playlist, true)
+type Res<T> = Result<T, crate::err::Error>;
+pub struct Scvr;
+impl Provider for Scvr {
+    fn name(&self) -> &'static str {
+        "SCVR"
+    }
+    fn referer(&self) -> &'static str {
+        "https://vr.stripchat.com/"
+    }
+    fn container(&self) -> Container {
+        Container::Mp4
+    }
+    fn get_playlist(
+        &self,
+        username: &str,
+        options: &ModelOptions,
+        settings: Arc<Settings>,
+    ) -> Res<Room> {
+        sc::sc_get_playlist(username, true, options, settings)
+    }
+    fn parse_playlist(&self, playlist: &mut Playlist) -> Res<Vec<Stream>> {
+        sc::sc_parse_playlist(playlist)
+    }
 }
@@ -1,25 +1,53 @@
 use {
-    crate::{config::Settings, e, o, platforms::Platform, s, stream, util},
+    crate::{
+        config::Settings,
+        e, hls, o,
+        platforms::{Container, ModelOptions, Platform, Provider, Room, RoomState},
+        s, stream, util,
+    },
     std::{
         sync::{Arc, OnceLock},
         *,
     },
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
+type Res<T> = Result<T, crate::err::Error>;
+pub struct Soda;
+impl Provider for Soda {
+    fn name(&self) -> &'static str {
+        "SODA"
+    }
+    fn referer(&self) -> &'static str {
+        "https://www.camsoda.com/"
+    }
+    fn filename_prefix(&self) -> &'static str {
+        "CS"
+    }
+    fn container(&self) -> Container {
+        Container::Mp4
+    }
+    fn get_playlist(
+        &self,
+        username: &str,
+        options: &ModelOptions,
+        settings: Arc<Settings>,
+    ) -> Res<Room> {
+        get_playlist(username, options, settings)
+    }
+    fn parse_playlist(&self, playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+        parse_playlist(playlist)
+    }
+}
 static REGEX_GET: OnceLock<Arc<regex::Regex>> = OnceLock::new();
-pub fn get_playlist(
-    username: &str,
-    settings: Arc<Settings>,
-) -> Res<(Option<String>, Option<String>)> {
+fn get_playlist(username: &str, options: &ModelOptions, settings: Arc<Settings>) -> Res<Room> {
     let headers = util::create_headers(serde_json::json!({
         "user-agent": &settings.user_agent,
-        "referer": format!("{}{}",Platform::SODA.referer(),username),
+        "referer": format!("{}{}",Platform(&Soda).referer(),username),
 
     }))
     .map_err(s!())?;
     // get model playlist link
     let url = format!("https://www.camsoda.com/{}", username);
-    let html = util::get_retry(&url, 1, Some(&headers)).map_err(s!())?;
+    let html = util::get_retry(&Platform(&Soda), &url, 1, Some(&headers)).map_err(s!())?;
     let re: &Arc<regex::Regex> =
         REGEX_GET.get_or_init(|| regex::Regex::new(r#""stream":[^\}]+\}"#).unwrap().into());
     let json_string = re.find(&html).ok_or_else(o!())?.as_str();
@@ -32,7 +60,7 @@ pub fn get_playlist(
         .as_array()
         .ok_or_else(o!())?;
     if hostname_array.len() == 0 {
-        return Ok((None, None));
+        return Ok(Room::new(RoomState::Offline));
     }
     let hostname = hostname_array[0].as_str().ok_or_else(o!())?;
     let stream_name = json
@@ -45,88 +73,59 @@ pub fn get_playlist(
         .ok_or_else(o!())?
         .as_str()
         .ok_or_else(o!())?;
-    let playlist_url = format!(
-        "https://{}/{}_v1/index.ll.m3u8?multitrack=true&filter=tracks:v4v3v2v1a1a2&token={}",
+    let master_url = format!(
+        "https://{}/{}_v1/index.ll.m3u8?multitrack=true&token={}",
         hostname, stream_name, token
     );
     // get playlist of resolutions
-    let playlist = util::get_retry(&playlist_url, 1, Some(&headers)).map_err(s!())?;
-    for line in playlist.lines() {
-        if line.len() < 5 || &line[..1] == "#" {
-            continue;
-        }
-        return Ok((Some(line.into()), None));
-    }
-    Ok((None, None))
+    let playlist =
+        util::get_retry(&Platform(&Soda), &master_url, 1, Some(&headers)).map_err(s!())?;
+    let preference = settings.variant_preference(&Platform(&Soda), options);
+    let master = hls::MasterPlaylist::parse(&playlist);
+    let Some(variant) = master.select(&preference) else {
+        return Ok(Room::public(None, None));
+    };
+    let Some(tracks) = tracks(&variant.uri) else {
+        let url = hls::resolve(&master_url, &variant.uri);
+        return Ok(Room::public(Some(url), None).variant(Some(variant)));
+    };
+    // only the tracks of the selected variant
+    let filtered_url = format!(
+        "https://{}/{}_v1/index.ll.m3u8?multitrack=true&filter=tracks:{}&token={}",
+        hostname, stream_name, tracks, token
+    );
+    let playlist =
+        util::get_retry(&Platform(&Soda), &filtered_url, 1, Some(&headers)).map_err(s!())?;
+    let url = hls::MasterPlaylist::parse(&playlist)
+        .variants
+        .first()
+        .map(|v| hls::resolve(&filtered_url, &v.uri));
+    Ok(Room::public(url, None).variant(Some(variant)))
+}
+static REGEX_TRACKS: OnceLock<Arc<regex::Regex>> = OnceLock::new();
+/// flussonic track names of a variant ex. "v2a1" of "tracks-v2a1/index.ll.m3u8"
+fn tracks(uri: &str) -> Option<&str> {
+    let re =
+        REGEX_TRACKS.get_or_init(|| regex::Regex::new(r"tracks-((?:[va]\d+)+)").unwrap().into());
+    Some(re.captures(uri)?.get(1)?.as_str())
 }
 static REGEX_PARSE: OnceLock<Arc<regex::Regex>> = OnceLock::new();
-pub fn parse_playlist(playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+fn parse_playlist(playlist: &mut stream::Playlist) -> Res<Vec<stream::Stream>> {
+    let media = playlist.media(false).map_err(s!())?;
+    let filename = playlist.filename(&media);
     let mut streams = Vec::new();
-    let mut date: Option<String> = None;
-    for line in (playlist.playlist.as_ref()).ok_or_else(o!())?.lines() {
-        // parse MP4 header
-        if playlist.mp4_header.is_none() {
-            if line.contains("EXT-X-MAP:URI") {
-                // parse header url
-                let header_url_split = line.split("\"").collect::<Vec<&str>>();
-                if header_url_split.len() < 2 {
-                    return Err("can not get mp4 header file".into());
-                }
-                let header_url = format!(
-                    "{}/{}",
-                    util::url_prefix(&playlist.playlist_url, header_url_split[1])
-                        .ok_or_else(o!())?,
-                    header_url_split[1]
-                );
-                let http_headers = util::create_headers(serde_json::json!({
-                    "user-agent": &playlist.settings.user_agent,
-                    "referer": Platform::SODA.referer(),
-
-                }))
-                .map_err(s!())?;
-                let header =
-                    util::get_retry_vec(&header_url, 5, Some(&http_headers)).map_err(s!())?;
-                playlist.mp4_header = Some(sync::Arc::new(header))
-            }
-        }
-        // parse date and time
-        if date.is_none() {
-            if let Some(n) = line.find("TIME") {
-                if line.len() < 21 {
-                    return Err("error parsing date from playlist")?;
-                }
-                let t = (&line.get(n + 7..n + 21).ok_or_else(o!())?)
-                    .replace(":", "-")
-                    .replace("T", "_");
-                date = Some(t);
-            }
-        }
-        if line.len() == 0 || &line[..1] == "#" {
-            continue;
-        }
+    for segment in &media.segments {
         // parse stream id
         let re = REGEX_PARSE.get_or_init(|| regex::Regex::new(r"-(\d*).llhls.mp4").unwrap().into());
         let id = re
-            .captures(line)
+            .captures(&segment.uri)
             .ok_or_else(o!())?
             .get(1)
             .ok_or_else(o!())?
             .as_str()
-            .parse::<u32>()
+            .parse::<u64>()
             .map_err(e!())?;
-        // parse filenames
-        let date = date.as_ref().ok_or_else(o!())?;
-        let filename = format!("CS_{}_{}", playlist.username, date);
-        streams.push(stream::Stream::new(
-            &filename,
-            line.into(),
-            None,
-            id,
-            Platform::SODA,
-            playlist.settings.user_agent.clone(),
-            playlist.mp4_header.clone(),
-            None,
-        ));
+        streams.push(stream::Stream::new(playlist, &filename, id, segment, None));
     }
     Ok(streams)
 }
@@ -0,0 +1,434 @@
+//! merges fragmented mp4 recordings, ex. separate video and audio, into one file
+use {
+    super::*,
+    crate::{e, s},
+    std::{
+        collections::{HashMap, VecDeque},
+        io::Write,
+        path::Path,
+    },
+};
+/// a moof and the mdat boxes after it, which its sample offsets point into
+struct Fragment {
+    input: usize,
+    start: u64,
+    moof: u64,
+    end: u64,
+    /// earliest decode time of its tracks in seconds
+    time: f64,
+}
+/// init segment of an input, repeated before each of its segments
+struct Init {
+    ftyp: Vec<u8>,
+    moov: Vec<u8>,
+    /// timescale of each track
+    timescales: HashMap<u32, u32>,
+    /// first decode time of each track
+    first: HashMap<u32, u64>,
+}
+/// writes the fragments of the inputs interleaved by time after one init segment
+/// with the tracks of all inputs, timestamps start from zero
+pub fn merge(inputs: &[&Path], output: &Path, duration: f64) -> Res<()> {
+    let mut files = Vec::new();
+    let mut inits = Vec::new();
+    let mut fragments = Vec::new();
+    for (input, path) in inputs.iter().enumerate() {
+        let mut file = fs::File::open(path).map_err(e!())?;
+        let (init, found) = scan(&mut file, input).map_err(s!())?;
+        files.push(file);
+        inits.push(init);
+        fragments.push(found);
+    }
+    // track ids of the inputs numbered from 1 in order
+    let mut ids = HashMap::new();
+    for (input, init) in inits.iter().enumerate() {
+        for trak in traks(&init.moov).map_err(s!())? {
+            let id = track_id(&init.moov, &trak).map_err(s!())?;
+            ids.insert((input, id), ids.len() as u32 + 1);
+        }
+    }
+    // starts the earliest track at zero, keeping the others in sync
+    let start = inits
+        .iter()
+        .flat_map(|init| {
+            init.first
+                .iter()
+                .map(|(id, time)| *time as f64 / init.timescales[id] as f64)
+        })
+        .fold(f64::INFINITY, f64::min);
+    let mut shifts = HashMap::new();
+    for (input, init) in inits.iter().enumerate() {
+        for (id, time) in &init.first {
+            let shift = (start * init.timescales[id] as f64) as u64;
+            shifts.insert((input, *id), shift.min(*time));
+        }
+    }
+    let moov = merged_moov(&inits, &ids, duration).map_err(s!())?;
+    let mut out = io::BufWriter::new(fs::File::create(output).map_err(e!())?);
+    out.write_all(&inits[0].ftyp).map_err(e!())?;
+    out.write_all(&moov).map_err(e!())?;
+    let mut position = (inits[0].ftyp.len() + moov.len()) as u64;
+    for (sequence, fragment) in interleave(fragments).iter().enumerate() {
+        let file = &mut files[fragment.input];
+        let mut moof = read_at(file, fragment.start, fragment.moof).map_err(s!())?;
+        let patch = Patch {
+            input: fragment.input,
+            sequence: sequence as u32 + 1,
+            ids: &ids,
+            shifts: &shifts,
+            moved: position as i64 - fragment.start as i64,
+        };
+        patch.apply(&mut moof).map_err(s!())?;
+        out.write_all(&moof).map_err(e!())?;
+        let mut mdat = file.take(fragment.end - fragment.start - fragment.moof);
+        io::copy(&mut mdat, &mut out).map_err(e!())?;
+        position += fragment.end - fragment.start;
+    }
+    out.flush().map_err(e!())?;
+    Ok(())
+}
+/// fragments of each input in file order, interleaved by time
+/// a time going back within an input, ex. after a discontinuity, does not reorder the input
+fn interleave(inputs: Vec<Vec<Fragment>>) -> Vec<Fragment> {
+    let mut inputs: Vec<VecDeque<Fragment>> = inputs.into_iter().map(VecDeque::from).collect();
+    let mut merged = Vec::new();
+    // the first input wins ties, audio and video at the same time stay in input order
+    while let Some(earliest) = inputs
+        .iter_mut()
+        .filter(|fragments| !fragments.is_empty())
+        .min_by(|a, b| a[0].time.total_cmp(&b[0].time))
+    {
+        merged.extend(earliest.pop_front());
+    }
+    merged
+}
+/// finds the init segment and fragments of an input
+fn scan(file: &mut fs::File, input: usize) -> Res<(Init, Vec<Fragment>)> {
+    let len = file.metadata().map_err(e!())?.len();
+    let mut ftyp = None;
+    let mut init: Option<Init> = None;
+    let mut fragments: Vec<Fragment> = Vec::new();
+    let mut pos = 0;
+    // a box cut off by the end of the file is left out
+    while let Some(header) = read_header(file, pos, len).map_err(s!())? {
+        match &header.kind {
+            b"ftyp" if ftyp.is_none() => {
+                ftyp = Some(read_at(file, pos, header.size).map_err(s!())?)
+            }
+            b"moov" => {
+                let moov = read_at(file, pos, header.size).map_err(s!())?;
+                match &init {
+                    None => {
+                        init = Some(Init {
+                            ftyp: ftyp.clone().unwrap_or_default(),
+                            timescales: timescales(&moov).map_err(s!())?,
+                            moov,
+                            first: HashMap::new(),
+                        })
+                    }
+                    Some(init) if init.moov != moov => {
+                        return Err("init segment changed during the recording".into());
+                    }
+                    Some(_) => {}
+                }
+            }
+            b"moof" => {
+                let init = init
+                    .as_mut()
+                    .ok_or_else(|| invalid("fragment before the init segment"))?;
+                let moof = read_at(file, pos, header.size).map_err(s!())?;
+                let mut time = f64::INFINITY;
+                for (id, decode_time) in decode_times(&moof).map_err(s!())? {
+                    let timescale = *init
+                        .timescales
+                        .get(&id)
+                        .ok_or_else(|| invalid("fragment of an unknown track"))?;
+                    init.first.entry(id).or_insert(decode_time);
+                    time = time.min(decode_time as f64 / timescale as f64);
+                }
+                fragments.push(Fragment {
+                    input,
+                    start: pos,
+                    moof: header.size,
+                    end: pos + header.size,
+                    time,
+                });
+            }
+            b"mdat" | b"free" | b"skip" => {
+                if let Some(fragment) = fragments.last_mut()
+                    && fragment.end == pos
+                {
+                    fragment.end += header.size;
+                }
+            }
+            // styp, sidx, prft and emsg are dropped
+            _ => {}
+        }
+        pos += header.size;
+    }
+    let mut init = init.ok_or_else(|| invalid("no init segment"))?;
+    if init.ftyp.is_empty() {
+        init.ftyp = ftyp.unwrap_or_default();
+    }
+    // a fragment without data was cut off
+    fragments.retain(|f| f.end > f.start + f.moof);
+    Ok((init, fragments))
+}
+fn traks(moov: &[u8]) -> Res<Vec<Span>> {
+    let moov_box = find(moov, &root(moov), &[b"moov"])?.ok_or_else(|| invalid("no moov"))?;
+    Ok(boxes(moov, moov_box.body, moov_box.end)?
+        .into_iter()
+        .filter(|b| &b.kind == b"trak")
+        .collect())
+}
+/// position of the track id in a trak's tkhd
+fn track_id_pos(moov: &[u8], trak: &Span) -> Res<usize> {
+    let tkhd = find(moov, trak, &[b"tkhd"])?.ok_or_else(|| invalid("no tkhd"))?;
+    Ok(tkhd.body + if version(moov, &tkhd)? == 1 { 20 } else { 12 })
+}
+fn track_id(moov: &[u8], trak: &Span) -> Res<u32> {
+    u32_at(moov, track_id_pos(moov, trak)?)
+}
+/// timescale of each track of a moov
+fn timescales(moov: &[u8]) -> Res<HashMap<u32, u32>> {
+    let mut timescales = HashMap::new();
+    for trak in traks(moov)? {
+        let mdhd = find(moov, &trak, &[b"mdia", b"mdhd"])?.ok_or_else(|| invalid("no mdhd"))?;
+        let pos = mdhd.body + if version(moov, &mdhd)? == 1 { 20 } else { 12 };
+        let timescale = u32_at(moov, pos)?;
+        if timescale == 0 {
+            return Err(invalid("track without a timescale"));
+        }
+        timescales.insert(track_id(moov, &trak)?, timescale);
+    }
+    Ok(timescales)
+}
+/// decode time of each track fragment of a moof
+fn decode_times(moof: &[u8]) -> Res<Vec<(u32, u64)>> {
+    let moof_box = find(moof, &root(moof), &[b"moof"])?.ok_or_else(|| invalid("no moof"))?;
+    let mut times = Vec::new();
+    for traf in boxes(moof, moof_box.body, moof_box.end)? {
+        if &traf.kind != b"traf" {
+            continue;
+        }
+        let tfhd = find(moof, &traf, &[b"tfhd"])?.ok_or_else(|| invalid("no tfhd"))?;
+        let tfdt = find(moof, &traf, &[b"tfdt"])?
+            .ok_or_else(|| invalid("fragment without a decode time"))?;
+        let time = match version(moof, &tfdt)? {
+            1 => u64_at(moof, tfdt.body + 4)?,
+            _ => u32_at(moof, tfdt.body + 4)? as u64,
+        };
+        times.push((u32_at(moof, tfhd.body + 4)?, time));
+    }
+    Ok(times)
+}
+/// moov with the tracks of every input, renumbered
+fn merged_moov(inits: &[Init], ids: &HashMap<(usize, u32), u32>, duration: f64) -> Res<Vec<u8>> {
+    let first = &inits[0].moov;
+    let moov_box = find(first, &root(first), &[b"moov"])?.ok_or_else(|| invalid("no moov"))?;
+    let mvhd = find(first, &moov_box, &[b"mvhd"])?.ok_or_else(|| invalid("no mvhd"))?;
+    let mut body = first[mvhd.start..mvhd.end].to_vec();
+    let timescale_pos = mvhd.body - mvhd.start + if version(first, &mvhd)? == 1 { 20 } else { 12 };
+    let timescale = u32_at(&body, timescale_pos)?;
+    let next_track_id = body
+        .len()
+        .checked_sub(4)
+        .ok_or_else(|| invalid("mvhd too short"))?;
+    set_u32(&mut body, next_track_id, ids.len() as u32 + 1)?;
+    let mut trex = Vec::new();
+    for (input, init) in inits.iter().enumerate() {
+        let moov = &init.moov;
+        for trak in traks(moov)? {
+            let pos = track_id_pos(moov, &trak)?;
+            let id = ids[&(input, u32_at(moov, pos)?)];
+            let start = body.len();
+            body.extend_from_slice(&moov[trak.start..trak.end]);
+            set_u32(&mut body, start + pos - trak.start, id)?;
+        }
+        let moov_box = find(moov, &root(moov), &[b"moov"])?.ok_or_else(|| invalid("no moov"))?;
+        let mvex = find(moov, &moov_box, &[b"mvex"])?
+            .ok_or_else(|| invalid("init segment is not fragmented"))?;
+        for child in boxes(moov, mvex.body, mvex.end)? {
+            if &child.kind != b"trex" {
+                continue;
+            }
+            if let Some(id) = ids.get(&(input, u32_at(moov, child.body + 4)?)) {
+                let start = trex.len();
+                trex.extend_from_slice(&moov[child.start..child.end]);
+                set_u32(&mut trex, start + child.body - child.start + 4, *id)?;
+            }
+        }
+    }
+    write_box(&mut body, b"mvex", |mvex| {
+        if duration > 0.0 {
+            // lets players show the length without reading every fragment
+            write_full_box(mvex, b"mehd", 1, 0, |mehd| {
+                mehd.put_u64((duration * timescale as f64) as u64)
+            });
+        }
+        mvex.extend_from_slice(&trex);
+    });
+    // udta, meta and others of the first input
+    for child in boxes(first, moov_box.body, moov_box.end)? {
+        if !matches!(&child.kind, b"mvhd" | b"trak" | b"mvex") {
+            body.extend_from_slice(&first[child.start..child.end]);
+        }
+    }
+    let mut moov = Vec::new();
+    write_box(&mut moov, b"moov", |moov| moov.extend_from_slice(&body));
+    Ok(moov)
+}
+/// changes of a moof copied to the merged file
+struct Patch<'a> {
+    input: usize,
+    sequence: u32,
+    ids: &'a HashMap<(usize, u32), u32>,
+    shifts: &'a HashMap<(usize, u32), u64>,
+    /// bytes the fragment moved by
+    moved: i64,
+}
+impl Patch<'_> {
+    /// renumbers the sequence and tracks, shifts decode times and moves absolute offsets
+    fn apply(&self, moof: &mut [u8]) -> Res<()> {
+        let moof_box = find(moof, &root(moof), &[b"moof"])?.ok_or_else(|| invalid("no moof"))?;
+        for child in boxes(moof, moof_box.body, moof_box.end)? {
+            match &child.kind {
+                b"mfhd" => set_u32(moof, child.body + 4, self.sequence)?,
+                b"traf" => {
+                    let tfhd = find(moof, &child, &[b"tfhd"])?.ok_or_else(|| invalid("no tfhd"))?;
+                    let flags = u32_at(moof, tfhd.body)? & 0xFFFFFF;
+                    let id = u32_at(moof, tfhd.body + 4)?;
+                    let key = (self.input, id);
+                    let new_id = self
+                        .ids
+                        .get(&key)
+                        .ok_or_else(|| invalid("fragment of an unknown track"))?;
+                    set_u32(moof, tfhd.body + 4, *new_id)?;
+                    // base data offset present, counted from the start of the file
+                    if flags & 1 != 0 {
+                        let offset = u64_at(moof, tfhd.body + 8)?;
+                        set_u64(moof, tfhd.body + 8, (offset as i64 + self.moved) as u64)?;
+                    }
+                    if let Some(tfdt) = find(moof, &child, &[b"tfdt"])? {
+                        let shift = self.shifts.get(&key).copied().unwrap_or_default();
+                        match version(moof, &tfdt)? {
+                            1 => {
+                                let time = u64_at(moof, tfdt.body + 4)?;
+                                set_u64(moof, tfdt.body + 4, time.saturating_sub(shift))?;
+                            }
+                            _ => {
+                                let time = u32_at(moof, tfdt.body + 4)? as u64;
+                                set_u32(moof, tfdt.body + 4, time.saturating_sub(shift) as u32)?;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    /// a moof of one track with a base data offset and a decode time
+    fn moof(sequence: u32, id: u32, offset: u64, tfdt_version: u8, time: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        write_box(&mut data, b"moof", |out| {
+            write_full_box(out, b"mfhd", 0, 0, |out| out.put_u32(sequence));
+            write_box(out, b"traf", |out| {
+                write_full_box(out, b"tfhd", 0, 1, |out| {
+                    out.put_u32(id);
+                    out.put_u64(offset);
+                });
+                write_full_box(out, b"tfdt", tfdt_version, 0, |out| match tfdt_version {
+                    1 => out.put_u64(time),
+                    _ => out.put_u32(time as u32),
+                });
+            });
+        });
+        data
+    }
+    fn fragment(input: usize, time: f64) -> Fragment {
+        Fragment {
+            input,
+            start: 0,
+            moof: 0,
+            end: 0,
+            time,
+        }
+    }
+    #[test]
+    fn patch_moof() {
+        let ids = HashMap::from([((0, 1), 1), ((1, 1), 2)]);
+        let shifts = HashMap::from([((1, 1), 1000)]);
+        for tfdt_version in [0, 1] {
+            let mut data = moof(5, 1, 4000, tfdt_version, 90000);
+            let patch = Patch {
+                input: 1,
+                sequence: 12,
+                ids: &ids,
+                shifts: &shifts,
+                moved: -1500,
+            };
+            patch.apply(&mut data).unwrap();
+            assert_eq!(data, moof(12, 2, 2500, tfdt_version, 89000));
+        }
+        // tracks of the first input keep their id and time
+        let mut data = moof(1, 1, 100, 1, 90000);
+        let patch = Patch {
+            input: 0,
+            sequence: 1,
+            ids: &ids,
+            shifts: &shifts,
+            moved: 200,
+        };
+        patch.apply(&mut data).unwrap();
+        assert_eq!(data, moof(1, 1, 300, 1, 90000));
+        // a track not in the init segments
+        let mut data = moof(1, 3, 0, 1, 0);
+        assert!(patch.apply(&mut data).is_err());
+    }
+    #[test]
+    fn patch_truncated_moof() {
+        let ids = HashMap::from([((0, 1), 1)]);
+        let shifts = HashMap::new();
+        let patch = Patch {
+            input: 0,
+            sequence: 1,
+            ids: &ids,
+            shifts: &shifts,
+            moved: 0,
+        };
+        let mut data = Vec::new();
+        write_box(&mut data, b"moof", |out| {
+            write_box(out, b"traf", |out| {
+                write_full_box(out, b"tfhd", 0, 0, |out| out.put_u32(1));
+                write_box(out, b"tfdt", |_| {});
+            });
+        });
+        assert!(patch.apply(&mut data).is_err());
+    }
+    #[test]
+    fn interleave_keeps_input_order() {
+        // the video input goes back in time after a discontinuity
+        let video = [0.0, 2.0, 4.0, 1.0, 3.0].map(|time| fragment(0, time));
+        let audio = [0.0, 1.5, 3.5].map(|time| fragment(1, time));
+        let merged = interleave(vec![video.into(), audio.into()]);
+        let order: Vec<(usize, f64)> = merged.iter().map(|f| (f.input, f.time)).collect();
+        assert_eq!(
+            order,
+            [
+                (0, 0.0),
+                (1, 0.0),
+                (1, 1.5),
+                (0, 2.0),
+                (1, 3.5),
+                (0, 4.0),
+                (0, 1.0),
+                (0, 3.0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! remuxes recordings to mp4 without ffmpeg
+mod fmp4;
+mod ts;
+use {
+    crate::{e, err::Error},
+    std::{
+        io::{Read, Seek},
+        *,
+    },
+};
+pub use {fmp4::merge, ts::convert};
+type Res<T> = Result<T, crate::err::Error>;
+/// rotation matrix of tkhd and mvhd, no rotation
+const MATRIX: [u32; 9] = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000];
+fn invalid(message: &str) -> Error {
+    Error::Parse(message.to_string())
+}
+/// position of a box in a buffer
+#[derive(Clone, Copy)]
+struct Span {
+    kind: [u8; 4],
+    start: usize,
+    /// start of the content after the header
+    body: usize,
+    end: usize,
+}
+/// boxes between start and end of data
+fn boxes(data: &[u8], start: usize, end: usize) -> Res<Vec<Span>> {
+    let mut spans = Vec::new();
+    let mut pos = start;
+    while pos + 8 <= end {
+        let size = u32_at(data, pos)? as usize;
+        let kind: [u8; 4] = data[pos + 4..pos + 8]
+            .try_into()
+            .map_err(|_| invalid("box"))?;
+        let (body, size) = match size {
+            0 => (pos + 8, end - pos),
+            1 => (pos + 16, u64_at(data, pos + 8)? as usize),
+            size => (pos + 8, size),
+        };
+        // a corrupt 64 bit size can overflow the end
+        if size < body - pos || pos.checked_add(size).is_none_or(|e| e > end) {
+            return Err(invalid("box larger than its parent"));
+        }
+        spans.push(Span {
+            kind,
+            start: pos,
+            body,
+            end: pos + size,
+        });
+        pos += size;
+    }
+    Ok(spans)
+}
+/// first child of parent with the kind, a path for nested boxes ex. ["mdia", "mdhd"]
+fn find(data: &[u8], parent: &Span, path: &[&[u8; 4]]) -> Res<Option<Span>> {
+    let mut span = *parent;
+    for kind in path {
+        match boxes(data, span.body, span.end)?
+            .into_iter()
+            .find(|s| &s.kind == *kind)
+        {
+            Some(child) => span = child,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(span))
+}
+/// the whole buffer as a box, for finding its children
+fn root(data: &[u8]) -> Span {
+    Span {
+        kind: *b"root",
+        start: 0,
+        body: 0,
+        end: data.len(),
+    }
+}
+/// version of a full box
+fn version(data: &[u8], span: &Span) -> Res<u8> {
+    match span.body < span.end {
+        true => data
+            .get(span.body)
+            .copied()
+            .ok_or_else(|| invalid("box too short")),
+        false => Err(invalid("box too short")),
+    }
+}
+fn u32_at(data: &[u8], pos: usize) -> Res<u32> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .ok_or_else(|| invalid("box too short"))?;
+    Ok(u32::from_be_bytes(
+        bytes.try_into().map_err(|_| invalid("box"))?,
+    ))
+}
+fn u64_at(data: &[u8], pos: usize) -> Res<u64> {
+    let bytes = data
+        .get(pos..pos + 8)
+        .ok_or_else(|| invalid("box too short"))?;
+    Ok(u64::from_be_bytes(
+        bytes.try_into().map_err(|_| invalid("box"))?,
+    ))
+}
+fn set_u32(data: &mut [u8], pos: usize, value: u32) -> Res<()> {
+    let bytes = data
+        .get_mut(pos..pos + 4)
+        .ok_or_else(|| invalid("box too short"))?;
+    bytes.copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+fn set_u64(data: &mut [u8], pos: usize, value: u64) -> Res<()> {
+    let bytes = data
+        .get_mut(pos..pos + 8)
+        .ok_or_else(|| invalid("box too short"))?;
+    bytes.copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+/// big endian writes
+trait Put {
+    fn put_u16(&mut self, value: u16);
+    fn put_u32(&mut self, value: u32);
+    fn put_u64(&mut self, value: u64);
+}
+impl Put for Vec<u8> {
+    fn put_u16(&mut self, value: u16) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+    fn put_u32(&mut self, value: u32) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+    fn put_u64(&mut self, value: u64) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+}
+/// appends a box, its size set after content writes the body
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], content: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.put_u32(0);
+    out.extend_from_slice(kind);
+    content(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+/// appends a box with a version and flags
+fn write_full_box(
+    out: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    content: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, kind, |out| {
+        out.put_u32((version as u32) << 24 | flags & 0xFFFFFF);
+        content(out);
+    });
+}
+/// header of a box in a file
+struct Header {
+    kind: [u8; 4],
+    size: u64,
+}
+/// reads the header of the box at pos, none if the file ends before the box does
+fn read_header(file: &mut fs::File, pos: u64, len: u64) -> Res<Option<Header>> {
+    if pos + 8 > len {
+        return Ok(None);
+    }
+    let mut header = [0u8; 16];
+    file.seek(io::SeekFrom::Start(pos)).map_err(e!())?;
+    file.read_exact(&mut header[..8]).map_err(e!())?;
+    let kind = header[4..8].try_into().map_err(|_| invalid("box"))?;
+    let size = match u32_at(&header, 0)? {
+        0 => len - pos,
+        1 if pos + 16 <= len => {
+            file.read_exact(&mut header[8..]).map_err(e!())?;
+            u64_at(&header, 8)?
+        }
+        1 => return Ok(None),
+        size => size as u64,
+    };
+    if size < 8 {
+        return Err(invalid("box smaller than its header"));
+    }
+    match pos.checked_add(size).is_some_and(|e| e <= len) {
+        true => Ok(Some(Header { kind, size })),
+        false => Ok(None),
+    }
+}
+/// reads size bytes at pos
+fn read_at(file: &mut fs::File, pos: u64, size: u64) -> Res<Vec<u8>> {
+    let mut data = vec![0u8; size as usize];
+    file.seek(io::SeekFrom::Start(pos)).map_err(e!())?;
+    file.read_exact(&mut data).map_err(e!())?;
+    Ok(data)
+}
+#[cfg(test)]
+mod tests {
+    use {super::*, std::io::Write};
+    /// a file in the temp directory, removed when dropped
+    pub(super) struct TempFile(pub(super) path::PathBuf);
+    impl TempFile {
+        pub(super) fn new(name: &str) -> Self {
+            Self(env::temp_dir().join(format!("cbstream-{}-{name}", process::id())))
+        }
+    }
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            _ = fs::remove_file(&self.0);
+        }
+    }
+    #[test]
+    fn boxes_of_truncated_data() {
+        let mut data = Vec::new();
+        write_box(&mut data, b"free", |out| out.extend_from_slice(&[0; 8]));
+        write_box(&mut data, b"mdat", |out| out.extend_from_slice(&[0; 8]));
+        let spans = boxes(&data, 0, data.len()).unwrap();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(&spans[1].kind, b"mdat");
+        assert_eq!((spans[1].start, spans[1].body, spans[1].end), (16, 24, 32));
+        // the second box ends after the data
+        assert!(boxes(&data, 0, data.len() - 1).is_err());
+        // a header cut short is not a box
+        assert_eq!(boxes(&data, 0, 20).unwrap().len(), 1);
+        // a 64 bit size without its field
+        let mut large = Vec::new();
+        large.put_u32(1);
+        large.extend_from_slice(b"mdat");
+        assert!(boxes(&large, 0, large.len()).is_err());
+        // a 64 bit size past the end of memory
+        let mut huge = Vec::new();
+        huge.put_u32(1);
+        huge.extend_from_slice(b"mdat");
+        huge.put_u64(u64::MAX);
+        assert!(boxes(&huge, 0, huge.len()).is_err());
+        // a size smaller than the header
+        let mut small = Vec::new();
+        small.put_u32(4);
+        small.extend_from_slice(b"free");
+        assert!(boxes(&small, 0, small.len()).is_err());
+    }
+    #[test]
+    fn version_of_empty_box() {
+        let mut data = Vec::new();
+        write_box(&mut data, b"tfdt", |_| {});
+        let span = boxes(&data, 0, data.len()).unwrap()[0];
+        assert!(version(&data, &span).is_err());
+        let mut data = Vec::new();
+        write_full_box(&mut data, b"tfdt", 1, 0, |out| out.put_u64(0));
+        let span = boxes(&data, 0, data.len()).unwrap()[0];
+        assert_eq!(version(&data, &span).unwrap(), 1);
+    }
+    #[test]
+    fn read_header_of_truncated_file() {
+        let temp = TempFile::new("header.mp4");
+        let mut data = Vec::new();
+        write_box(&mut data, b"ftyp", |out| out.extend_from_slice(b"isom"));
+        // a 64 bit size mdat of 32 bytes, cut after 24
+        data.put_u32(1);
+        data.extend_from_slice(b"mdat");
+        data.put_u64(32);
+        data.extend_from_slice(&[0; 8]);
+        fs::File::create(&temp.0).unwrap().write_all(&data).unwrap();
+        let mut file = fs::File::open(&temp.0).unwrap();
+        let len = data.len() as u64;
+        let header = read_header(&mut file, 0, len).unwrap().unwrap();
+        assert_eq!((&header.kind, header.size), (b"ftyp", 12));
+        assert!(read_header(&mut file, 12, len).unwrap().is_none());
+        // the 64 bit size itself is cut
+        assert!(read_header(&mut file, 12, 22).unwrap().is_none());
+        // a 64 bit size past the end of any file
+        let huge = TempFile::new("huge.mp4");
+        let mut data = Vec::new();
+        data.put_u32(1);
+        data.extend_from_slice(b"mdat");
+        data.put_u64(u64::MAX);
+        fs::write(&huge.0, &data).unwrap();
+        let mut huge_file = fs::File::open(&huge.0).unwrap();
+        assert!(read_header(&mut huge_file, 0, 16).unwrap().is_none());
+        // less than a header left
+        assert!(read_header(&mut file, len - 4, len).unwrap().is_none());
+        // the rest of the box written later
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&temp.0)
+            .unwrap()
+            .write_all(&[0; 8])
+            .unwrap();
+        let header = read_header(&mut file, 12, len + 8).unwrap().unwrap();
+        assert_eq!((&header.kind, header.size), (b"mdat", 32));
+    }
+}
@@ -0,0 +1,1030 @@
+//! converts mpeg-ts recordings with h.264 video and aac audio to mp4
+use {
+    super::*,
+    crate::{e, s},
+    std::{io::Write, path::Path},
+};
+const PACKET: usize = 188;
+const SYNC: u8 = 0x47;
+/// clock of pes timestamps
+const CLOCK: i64 = 90000;
+/// timescale of the mp4 movie, milliseconds
+const MOVIE_TIMESCALE: u64 = 1000;
+/// samples of an aac frame
+const AAC_FRAME: i64 = 1024;
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Video,
+    Audio,
+}
+impl Kind {
+    /// kind of an elementary stream type, with whether it can be converted
+    fn of(stream_type: u8) -> Option<(Kind, bool)> {
+        match stream_type {
+            0x1B => Some((Kind::Video, true)),
+            0x0F => Some((Kind::Audio, true)),
+            0x01 | 0x02 | 0x10 | 0x24 | 0x42 | 0xEA => Some((Kind::Video, false)),
+            0x03 | 0x04 | 0x11 | 0x81 | 0x87 => Some((Kind::Audio, false)),
+            _ => None,
+        }
+    }
+}
+struct Sample {
+    size: u32,
+    /// decode time in the track's timescale
+    dts: i64,
+    /// presentation time after the decode time
+    cts: i64,
+    key: bool,
+}
+/// samples of a track and where they were written
+#[derive(Default)]
+struct Track {
+    samples: Vec<Sample>,
+    /// offset and sample count of each run of samples written together
+    chunks: Vec<(u64, u32)>,
+    timeline: Timeline,
+    /// presentation time of the first sample on the pes clock
+    start: Option<i64>,
+    /// pes being received
+    pes: Option<Vec<u8>>,
+    pid: Option<u16>,
+}
+/// keeps timestamps increasing across wraps and discontinuities
+#[derive(Default)]
+struct Timeline {
+    offset: i64,
+    last: Option<i64>,
+}
+impl Timeline {
+    /// a timestamp that jumps back or more than a minute ahead continues step after the previous
+    fn map(&mut self, raw: i64, step: i64) -> i64 {
+        let mut time = raw + self.offset;
+        if let Some(last) = self.last {
+            if time < last - CLOCK || time > last + 60 * CLOCK {
+                self.offset += last + step - time;
+                time = last + step;
+            } else if time <= last {
+                time = last + 1;
+            }
+        }
+        self.last = Some(time);
+        time
+    }
+}
+#[derive(PartialEq)]
+struct AudioConfig {
+    /// audio object type
+    object: u8,
+    rate_index: u8,
+    channels: u8,
+}
+impl AudioConfig {
+    fn rate(&self) -> i64 {
+        SAMPLE_RATES[self.rate_index as usize] as i64
+    }
+}
+struct Converter {
+    out: io::BufWriter<fs::File>,
+    position: u64,
+    /// track written last, a new chunk starts when it changes
+    last: Option<Kind>,
+    pmt_pids: Vec<u16>,
+    video: Track,
+    audio: Track,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    audio_config: Option<AudioConfig>,
+    /// start of an aac frame continued in the next pes, with its time
+    audio_pending: (Vec<u8>, i64),
+}
+/// converts the h.264 and aac streams of input to an mp4 with the index at the end
+pub fn convert(input: &Path, output: &Path) -> Res<()> {
+    let mut out = io::BufWriter::new(fs::File::create(output).map_err(e!())?);
+    let ftyp = ftyp();
+    out.write_all(&ftyp).map_err(e!())?;
+    // 64 bit size set once the samples are written
+    out.write_all(&[0, 0, 0, 1, b'm', b'd', b'a', b't'])
+        .map_err(e!())?;
+    out.write_all(&[0; 8]).map_err(e!())?;
+    let mut converter = Converter::new(out, ftyp.len() as u64 + 16);
+    let mut reader = io::BufReader::with_capacity(1 << 20, fs::File::open(input).map_err(e!())?);
+    let mut packet = [0u8; PACKET];
+    'packets: while fill(&mut reader, &mut packet).map_err(s!())? {
+        // finds the next packet after data that is not a packet
+        while packet[0] != SYNC {
+            let skip = packet[1..]
+                .iter()
+                .position(|b| *b == SYNC)
+                .map_or(PACKET, |p| p + 1);
+            packet.copy_within(skip.., 0);
+            if !fill(&mut reader, &mut packet[PACKET - skip..]).map_err(s!())? {
+                break 'packets;
+            }
+        }
+        converter.packet(&packet).map_err(s!())?;
+    }
+    converter.flush(Kind::Video).map_err(s!())?;
+    converter.flush(Kind::Audio).map_err(s!())?;
+    converter.finish(ftyp.len() as u64).map_err(s!())
+}
+/// reads a whole buffer, false at the end of the file
+fn fill(reader: &mut impl io::Read, buf: &mut [u8]) -> Res<bool> {
+    match reader.read_exact(buf) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e).map_err(e!()),
+    }
+}
+fn ftyp() -> Vec<u8> {
+    let mut ftyp = Vec::new();
+    write_box(&mut ftyp, b"ftyp", |ftyp| {
+        ftyp.extend_from_slice(b"isom");
+        ftyp.put_u32(0x200);
+        ftyp.extend_from_slice(b"isomiso2avc1mp41");
+    });
+    ftyp
+}
+impl Converter {
+    /// samples are written to out from position on
+    fn new(out: io::BufWriter<fs::File>, position: u64) -> Self {
+        Self {
+            out,
+            position,
+            last: None,
+            pmt_pids: Vec::new(),
+            video: Track::default(),
+            audio: Track::default(),
+            sps: None,
+            pps: None,
+            audio_config: None,
+            audio_pending: (Vec::new(), 0),
+        }
+    }
+    fn track(&mut self, kind: Kind) -> &mut Track {
+        match kind {
+            Kind::Video => &mut self.video,
+            Kind::Audio => &mut self.audio,
+        }
+    }
+    fn packet(&mut self, packet: &[u8]) -> Res<()> {
+        // transport error
+        if packet[1] & 0x80 != 0 {
+            return Ok(());
+        }
+        let start = packet[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([packet[1] & 0x1F, packet[2]]);
+        let control = packet[3] >> 4 & 3;
+        let mut offset = 4;
+        // adaptation field
+        if control & 2 != 0 {
+            offset += 1 + packet[4] as usize;
+        }
+        if control & 1 == 0 || offset >= PACKET {
+            return Ok(());
+        }
+        let payload = &packet[offset..];
+        if pid == 0 {
+            if start {
+                self.pat(payload);
+            }
+            return Ok(());
+        }
+        if self.pmt_pids.contains(&pid) {
+            if start {
+                self.pmt(payload).map_err(s!())?;
+            }
+            return Ok(());
+        }
+        let kind = match pid {
+            _ if self.video.pid == Some(pid) => Kind::Video,
+            _ if self.audio.pid == Some(pid) => Kind::Audio,
+            _ => return Ok(()),
+        };
+        if start {
+            self.flush(kind).map_err(s!())?;
+            self.track(kind).pes = Some(payload.to_vec());
+        } else if let Some(pes) = self.track(kind).pes.as_mut() {
+            pes.extend_from_slice(payload);
+        }
+        Ok(())
+    }
+    fn pat(&mut self, payload: &[u8]) {
+        let Some(section) = section(payload) else {
+            return;
+        };
+        for program in section.chunks_exact(4) {
+            let number = u16::from_be_bytes([program[0], program[1]]);
+            let pid = u16::from_be_bytes([program[2] & 0x1F, program[3]]);
+            if number != 0 && !self.pmt_pids.contains(&pid) {
+                self.pmt_pids.push(pid);
+            }
+        }
+    }
+    /// picks the first video and audio stream of the program
+    fn pmt(&mut self, payload: &[u8]) -> Res<()> {
+        let Some(section) = section(payload) else {
+            return Ok(());
+        };
+        let Some(info) = section.get(2..4) else {
+            return Ok(());
+        };
+        let mut pos = 4 + (u16::from_be_bytes([info[0] & 0x0F, info[1]]) as usize);
+        let mut pids = [None, None];
+        let mut unsupported = [None, None];
+        while let Some(stream) = section.get(pos..pos + 5) {
+            let pid = u16::from_be_bytes([stream[1] & 0x1F, stream[2]]);
+            pos += 5 + u16::from_be_bytes([stream[3] & 0x0F, stream[4]]) as usize;
+            match Kind::of(stream[0]) {
+                Some((kind, true)) => {
+                    pids[kind as usize].get_or_insert(pid);
+                }
+                Some((kind, false)) => {
+                    unsupported[kind as usize].get_or_insert(stream[0]);
+                }
+                None => {}
+            }
+        }
+        for kind in [Kind::Video, Kind::Audio] {
+            if let (None, Some(stream_type)) = (pids[kind as usize], unsupported[kind as usize]) {
+                return Err(format!("unsupported stream type {:#x}", stream_type).into());
+            }
+            if let Some(pid) = pids[kind as usize]
+                && self.track(kind).pid != Some(pid)
+            {
+                self.flush(kind).map_err(s!())?;
+                self.track(kind).pid = Some(pid);
+            }
+        }
+        Ok(())
+    }
+    /// converts the pes received so far
+    fn flush(&mut self, kind: Kind) -> Res<()> {
+        let Some(data) = self.track(kind).pes.take() else {
+            return Ok(());
+        };
+        let Some(pes) = Pes::parse(&data) else {
+            return Ok(());
+        };
+        match kind {
+            Kind::Video => self.video_pes(pes),
+            Kind::Audio => self.audio_pes(pes),
+        }
+    }
+    /// an access unit, annex b nal units written with their lengths
+    fn video_pes(&mut self, pes: Pes) -> Res<()> {
+        let (Some(pts), Some(dts)) = (pes.pts, pes.dts) else {
+            return Err("video without timestamps".into());
+        };
+        let mut sample = Vec::with_capacity(pes.data.len() + 64);
+        let mut key = false;
+        for nal in nal_units(pes.data) {
+            match nal[0] & 0x1F {
+                // parameter sets go in the sample description
+                7 => parameter_set(&mut self.sps, nal).map_err(s!())?,
+                8 => parameter_set(&mut self.pps, nal).map_err(s!())?,
+                // access unit delimiter
+                9 => {}
+                kind => {
+                    key |= kind == 5;
+                    sample.put_u32(nal.len() as u32);
+                    sample.extend_from_slice(nal);
+                }
+            }
+        }
+        if sample.is_empty() {
+            return Ok(());
+        }
+        // starts at a keyframe the parameter sets were sent with
+        if self.video.samples.is_empty() && !(key && self.sps.is_some() && self.pps.is_some()) {
+            return Ok(());
+        }
+        let step = match self.video.samples.as_slice() {
+            [.., a, b] => b.dts - a.dts,
+            _ => 3000,
+        };
+        let time = self.video.timeline.map(dts, step);
+        let cts = (pts - dts).max(0);
+        self.video.start.get_or_insert(time + cts);
+        self.write(Kind::Video, &sample, time, cts, key)
+    }
+    /// aac frames without their adts headers
+    fn audio_pes(&mut self, pes: Pes) -> Res<()> {
+        let Some(pts) = pes.pts else {
+            return Err("audio without timestamps".into());
+        };
+        let (mut data, pending_time) = mem::take(&mut self.audio_pending);
+        let continued = !data.is_empty();
+        data.extend_from_slice(pes.data);
+        let mut pos = 0;
+        let mut frames = 0;
+        while pos + 7 <= data.len() {
+            let header = &data[pos..pos + 7];
+            if header[0] != 0xFF || header[1] & 0xF0 != 0xF0 {
+                pos += 1;
+                continue;
+            }
+            let header_len = if header[1] & 1 == 1 { 7 } else { 9 };
+            let frame_len = ((header[3] & 3) as usize) << 11
+                | (header[4] as usize) << 3
+                | (header[5] >> 5) as usize;
+            if frame_len <= header_len {
+                pos += 1;
+                continue;
+            }
+            let config = AudioConfig {
+                object: (header[2] >> 6) + 1,
+                rate_index: header[2] >> 2 & 0xF,
+                channels: (header[2] & 1) << 2 | header[3] >> 6,
+            };
+            if config.rate_index as usize >= SAMPLE_RATES.len() {
+                pos += 1;
+                continue;
+            }
+            if pos + frame_len > data.len() {
+                break;
+            }
+            if header[6] & 3 != 0 {
+                return Err("aac frames with several blocks are not supported".into());
+            }
+            match &self.audio_config {
+                Some(current) if *current != config => {
+                    return Err("audio format changed during the recording".into());
+                }
+                Some(_) => {}
+                None => self.audio_config = Some(config),
+            }
+            let rate = self
+                .audio_config
+                .as_ref()
+                .ok_or_else(no_audio_config)?
+                .rate();
+            let frame_clock = AAC_FRAME * CLOCK / rate;
+            // the pes timestamp is of the first frame that starts in it
+            let raw = match continued {
+                true if frames == 0 => pending_time,
+                true => pts + (frames - 1) * frame_clock,
+                false => pts + frames * frame_clock,
+            };
+            let time = self.audio.timeline.map(raw, frame_clock);
+            self.audio.start.get_or_insert(time);
+            // frames are counted in samples, timestamps only correct gaps and drift
+            let mut dts = time * rate / CLOCK;
+            if let Some(last) = self.audio.samples.last()
+                && (dts - last.dts - AAC_FRAME).abs() < AAC_FRAME / 2
+            {
+                dts = last.dts + AAC_FRAME;
+            }
+            let frame = data[pos + header_len..pos + frame_len].to_vec();
+            self.write(Kind::Audio, &frame, dts, 0, true)
+                .map_err(s!())?;
+            pos += frame_len;
+            frames += 1;
+        }
+        if pos < data.len() {
+            let rate = self.audio_config.as_ref().map_or(48000, AudioConfig::rate);
+            let frame_clock = AAC_FRAME * CLOCK / rate;
+            let time = match continued {
+                true if frames == 0 => pending_time,
+                true => pts + (frames - 1) * frame_clock,
+                false => pts + frames * frame_clock,
+            };
+            self.audio_pending = (data[pos..].to_vec(), time);
+        }
+        Ok(())
+    }
+    /// appends a sample to the mdat
+    fn write(&mut self, kind: Kind, data: &[u8], dts: i64, cts: i64, key: bool) -> Res<()> {
+        self.out.write_all(data).map_err(e!())?;
+        let position = self.position;
+        self.position += data.len() as u64;
+        let new_chunk = self.last != Some(kind);
+        self.last = Some(kind);
+        let track = self.track(kind);
+        match track.chunks.last_mut() {
+            Some((_, count)) if !new_chunk => *count += 1,
+            _ => track.chunks.push((position, 1)),
+        }
+        track.samples.push(Sample {
+            size: data.len() as u32,
+            dts,
+            cts,
+            key,
+        });
+        Ok(())
+    }
+    /// sets the size of the mdat and writes the moov after it
+    fn finish(mut self, ftyp_len: u64) -> Res<()> {
+        if self.video.samples.is_empty() && self.audio.samples.is_empty() {
+            return Err("no h.264 or aac samples".into());
+        }
+        let moov = self.moov().map_err(s!())?;
+        self.out.write_all(&moov).map_err(e!())?;
+        let mut file = self
+            .out
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .map_err(e!())?;
+        file.seek(io::SeekFrom::Start(ftyp_len + 8)).map_err(e!())?;
+        file.write_all(&(self.position - ftyp_len).to_be_bytes())
+            .map_err(e!())?;
+        Ok(())
+    }
+    fn moov(&self) -> Res<Vec<u8>> {
+        let tracks: Vec<(Kind, &Track)> = [(Kind::Video, &self.video), (Kind::Audio, &self.audio)]
+            .into_iter()
+            .filter(|(_, t)| !t.samples.is_empty())
+            .collect();
+        // tracks starting later are delayed by an empty edit
+        let start = tracks
+            .iter()
+            .filter_map(|(_, t)| t.start)
+            .min()
+            .unwrap_or_default();
+        let mut traks = Vec::new();
+        let mut movie_duration = 0;
+        for (id, (kind, track)) in tracks.iter().enumerate() {
+            let description = match kind {
+                Kind::Video => self.avc1().map_err(s!())?,
+                Kind::Audio => self.mp4a().map_err(s!())?,
+            };
+            let timescale = match kind {
+                Kind::Video => CLOCK as u64,
+                Kind::Audio => self
+                    .audio_config
+                    .as_ref()
+                    .ok_or_else(no_audio_config)?
+                    .rate() as u64,
+            };
+            let trak = Trak {
+                id: id as u32 + 1,
+                kind: *kind,
+                track,
+                timescale,
+                delay: (track.start.unwrap_or(start) - start) as u64,
+                description,
+                dimensions: match kind {
+                    Kind::Video => dimensions(self.sps.as_deref().unwrap_or_default())
+                        .ok_or_else(|| invalid("sps"))?,
+                    Kind::Audio => (0, 0),
+                },
+            };
+            movie_duration = movie_duration.max(trak.write(&mut traks));
+        }
+        let mut moov = Vec::new();
+        write_box(&mut moov, b"moov", |moov| {
+            write_full_box(moov, b"mvhd", 1, 0, |mvhd| {
+                mvhd.put_u64(0);
+                mvhd.put_u64(0);
+                mvhd.put_u32(MOVIE_TIMESCALE as u32);
+                mvhd.put_u64(movie_duration);
+                mvhd.put_u32(0x10000);
+                mvhd.put_u16(0x100);
+                mvhd.extend_from_slice(&[0; 10]);
+                MATRIX.iter().for_each(|m| mvhd.put_u32(*m));
+                mvhd.extend_from_slice(&[0; 24]);
+                mvhd.put_u32(tracks.len() as u32 + 1);
+            });
+            moov.extend_from_slice(&traks);
+        });
+        Ok(moov)
+    }
+    /// sample description of the video
+    fn avc1(&self) -> Res<Vec<u8>> {
+        let sps = self.sps.as_deref().ok_or_else(|| invalid("no sps"))?;
+        let pps = self.pps.as_deref().ok_or_else(|| invalid("no pps"))?;
+        let (width, height) = dimensions(sps).ok_or_else(|| invalid("sps"))?;
+        let mut avc1 = Vec::new();
+        write_box(&mut avc1, b"avc1", |avc1| {
+            avc1.extend_from_slice(&[0; 6]);
+            // data reference index
+            avc1.put_u16(1);
+            avc1.extend_from_slice(&[0; 16]);
+            avc1.put_u16(width as u16);
+            avc1.put_u16(height as u16);
+            // 72 dpi
+            avc1.put_u32(0x480000);
+            avc1.put_u32(0x480000);
+            avc1.put_u32(0);
+            // frame count
+            avc1.put_u16(1);
+            avc1.extend_from_slice(&[0; 32]);
+            // depth
+            avc1.put_u16(0x18);
+            avc1.put_u16(0xFFFF);
+            write_box(avc1, b"avcC", |avcc| {
+                avcc.push(1);
+                avcc.extend_from_slice(&sps[1..4]);
+                // 4 byte nal lengths
+                avcc.push(0xFF);
+                avcc.push(0xE1);
+                avcc.put_u16(sps.len() as u16);
+                avcc.extend_from_slice(sps);
+                avcc.push(1);
+                avcc.put_u16(pps.len() as u16);
+                avcc.extend_from_slice(pps);
+            });
+        });
+        Ok(avc1)
+    }
+    /// sample description of the audio
+    fn mp4a(&self) -> Res<Vec<u8>> {
+        let config = self.audio_config.as_ref().ok_or_else(no_audio_config)?;
+        let specific = (config.object as u16) << 11
+            | (config.rate_index as u16) << 7
+            | (config.channels as u16) << 3;
+        let mut mp4a = Vec::new();
+        write_box(&mut mp4a, b"mp4a", |mp4a| {
+            mp4a.extend_from_slice(&[0; 6]);
+            mp4a.put_u16(1);
+            mp4a.extend_from_slice(&[0; 8]);
+            mp4a.put_u16(config.channels.max(1) as u16);
+            // sample size
+            mp4a.put_u16(16);
+            mp4a.put_u32(0);
+            mp4a.put_u32((config.rate().min(u16::MAX as i64) as u32) << 16);
+            write_full_box(mp4a, b"esds", 0, 0, |esds| {
+                // es descriptor
+                esds.extend_from_slice(&[3, 25, 0, 0, 0]);
+                // decoder config, aac audio stream
+                esds.extend_from_slice(&[4, 17, 0x40, 0x15, 0, 0, 0]);
+                esds.put_u32(0);
+                esds.put_u32(0);
+                // audio specific config
+                esds.extend_from_slice(&[5, 2]);
+                esds.put_u16(specific);
+                // sl config
+                esds.extend_from_slice(&[6, 1, 2]);
+            });
+        });
+        Ok(mp4a)
+    }
+}
+fn no_audio_config() -> Error {
+    invalid("no audio config")
+}
+/// a track written to the moov
+struct Trak<'a> {
+    id: u32,
+    kind: Kind,
+    track: &'a Track,
+    timescale: u64,
+    /// time on the pes clock before the track starts
+    delay: u64,
+    description: Vec<u8>,
+    dimensions: (u32, u32),
+}
+impl Trak<'_> {
+    /// appends the trak, returns its duration in the movie timescale
+    fn write(&self, out: &mut Vec<u8>) -> u64 {
+        let samples = &self.track.samples;
+        let mut durations: Vec<u32> = samples
+            .windows(2)
+            .map(|w| (w[1].dts - w[0].dts).max(1) as u32)
+            .collect();
+        let last = match self.kind {
+            Kind::Video => durations.last().copied().unwrap_or(3000),
+            Kind::Audio => AAC_FRAME as u32,
+        };
+        durations.push(last);
+        let media_duration: u64 = durations.iter().map(|d| *d as u64).sum();
+        let first_cts = samples[0].cts as u64;
+        let delay = self.delay * MOVIE_TIMESCALE / CLOCK as u64;
+        let edit = media_duration.saturating_sub(first_cts) * MOVIE_TIMESCALE / self.timescale;
+        let duration = delay + edit;
+        write_box(out, b"trak", |trak| {
+            write_full_box(trak, b"tkhd", 1, 3, |tkhd| {
+                tkhd.put_u64(0);
+                tkhd.put_u64(0);
+                tkhd.put_u32(self.id);
+                tkhd.put_u32(0);
+                tkhd.put_u64(duration);
+                tkhd.extend_from_slice(&[0; 8]);
+                // layer and alternate group
+                tkhd.put_u32(0);
+                tkhd.put_u16(if self.kind == Kind::Audio { 0x100 } else { 0 });
+                tkhd.put_u16(0);
+                MATRIX.iter().for_each(|m| tkhd.put_u32(*m));
+                tkhd.put_u32(self.dimensions.0 << 16);
+                tkhd.put_u32(self.dimensions.1 << 16);
+            });
+            write_box(trak, b"edts", |edts| {
+                write_full_box(edts, b"elst", 1, 0, |elst| {
+                    elst.put_u32(if delay > 0 { 2 } else { 1 });
+                    if delay > 0 {
+                        elst.put_u64(delay);
+                        elst.put_u64(u64::MAX);
+                        elst.put_u32(0x10000);
+                    }
+                    elst.put_u64(edit);
+                    // the first sample is presented after its composition offset
+                    elst.put_u64(first_cts);
+                    elst.put_u32(0x10000);
+                });
+            });
+            write_box(trak, b"mdia", |mdia| {
+                write_full_box(mdia, b"mdhd", 1, 0, |mdhd| {
+                    mdhd.put_u64(0);
+                    mdhd.put_u64(0);
+                    mdhd.put_u32(self.timescale as u32);
+                    mdhd.put_u64(media_duration);
+                    // undetermined language
+                    mdhd.put_u16(0x55C4);
+                    mdhd.put_u16(0);
+                });
+                let (handler, name): (&[u8; 4], &[u8]) = match self.kind {
+                    Kind::Video => (b"vide", b"VideoHandler\0"),
+                    Kind::Audio => (b"soun", b"SoundHandler\0"),
+                };
+                write_full_box(mdia, b"hdlr", 0, 0, |hdlr| {
+                    hdlr.put_u32(0);
+                    hdlr.extend_from_slice(handler);
+                    hdlr.extend_from_slice(&[0; 12]);
+                    hdlr.extend_from_slice(name);
+                });
+                write_box(mdia, b"minf", |minf| {
+                    match self.kind {
+                        Kind::Video => write_full_box(minf, b"vmhd", 0, 1, |vmhd| {
+                            vmhd.extend_from_slice(&[0; 8])
+                        }),
+                        Kind::Audio => write_full_box(minf, b"smhd", 0, 0, |smhd| {
+                            smhd.extend_from_slice(&[0; 4])
+                        }),
+                    }
+                    write_box(minf, b"dinf", |dinf| {
+                        write_full_box(dinf, b"dref", 0, 0, |dref| {
+                            dref.put_u32(1);
+                            // the samples are in this file
+                            write_full_box(dref, b"url ", 0, 1, |_| {});
+                        });
+                    });
+                    write_box(minf, b"stbl", |stbl| self.write_stbl(stbl, &durations));
+                });
+            });
+        });
+        duration
+    }
+    /// sample tables
+    fn write_stbl(&self, stbl: &mut Vec<u8>, durations: &[u32]) {
+        let samples = &self.track.samples;
+        write_full_box(stbl, b"stsd", 0, 0, |stsd| {
+            stsd.put_u32(1);
+            stsd.extend_from_slice(&self.description);
+        });
+        write_full_box(stbl, b"stts", 0, 0, |stts| {
+            write_runs(stts, durations.iter().copied());
+        });
+        if samples.iter().any(|s| s.cts != 0) {
+            write_full_box(stbl, b"ctts", 0, 0, |ctts| {
+                write_runs(ctts, samples.iter().map(|s| s.cts as u32));
+            });
+        }
+        if samples.iter().any(|s| !s.key) {
+            let keys: Vec<u32> = (1..=samples.len() as u32)
+                .filter(|i| samples[*i as usize - 1].key)
+                .collect();
+            write_full_box(stbl, b"stss", 0, 0, |stss| {
+                stss.put_u32(keys.len() as u32);
+                keys.iter().for_each(|k| stss.put_u32(*k));
+            });
+        }
+        let chunks = &self.track.chunks;
+        write_full_box(stbl, b"stsc", 0, 0, |stsc| {
+            let mut runs: Vec<(u32, u32)> = Vec::new();
+            for (i, (_, count)) in chunks.iter().enumerate() {
+                if runs.last().is_none_or(|(_, c)| c != count) {
+                    runs.push((i as u32 + 1, *count));
+                }
+            }
+            stsc.put_u32(runs.len() as u32);
+            for (first, count) in runs {
+                stsc.put_u32(first);
+                stsc.put_u32(count);
+                stsc.put_u32(1);
+            }
+        });
+        write_full_box(stbl, b"stsz", 0, 0, |stsz| {
+            stsz.put_u32(0);
+            stsz.put_u32(samples.len() as u32);
+            samples.iter().for_each(|s| stsz.put_u32(s.size));
+        });
+        let large = chunks.last().is_some_and(|(o, _)| *o > u32::MAX as u64);
+        let kind = if large { b"co64" } else { b"stco" };
+        write_full_box(stbl, kind, 0, 0, |stco| {
+            stco.put_u32(chunks.len() as u32);
+            for (offset, _) in chunks {
+                match large {
+                    true => stco.put_u64(*offset),
+                    false => stco.put_u32(*offset as u32),
+                }
+            }
+        });
+    }
+}
+/// run length encoded values, a count and the value of each run
+fn write_runs(out: &mut Vec<u8>, values: impl Iterator<Item = u32>) {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((count, v)) if *v == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    out.put_u32(runs.len() as u32);
+    for (count, value) in runs {
+        out.put_u32(count);
+        out.put_u32(value);
+    }
+}
+/// psi section of a pat or pmt after its header, without the crc
+fn section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    let length = u16::from_be_bytes([section.get(1)? & 0x0F, *section.get(2)?]) as usize;
+    section.get(8..(3 + length).checked_sub(4)?)
+}
+struct Pes<'a> {
+    pts: Option<i64>,
+    dts: Option<i64>,
+    data: &'a [u8],
+}
+impl<'a> Pes<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.get(..3)? != [0, 0, 1] {
+            return None;
+        }
+        let length = u16::from_be_bytes([*data.get(4)?, *data.get(5)?]) as usize;
+        let data = match length {
+            0 => data,
+            length => data.get(..6 + length).unwrap_or(data),
+        };
+        let flags = *data.get(7)?;
+        let header_len = *data.get(8)? as usize;
+        let pts = match flags & 0x80 {
+            0 => None,
+            _ => Some(timestamp(data.get(9..14)?)),
+        };
+        let dts = match flags & 0xC0 {
+            0xC0 => Some(timestamp(data.get(14..19)?)),
+            _ => pts,
+        };
+        Some(Self {
+            pts,
+            dts,
+            data: data.get(9 + header_len..)?,
+        })
+    }
+}
+/// 33 bit timestamp of a pes header
+fn timestamp(b: &[u8]) -> i64 {
+    (b[0] as i64 >> 1 & 7) << 30
+        | (b[1] as i64) << 22
+        | (b[2] as i64 >> 1) << 15
+        | (b[3] as i64) << 7
+        | b[4] as i64 >> 1
+}
+/// nal units of an annex b byte stream
+fn nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    let mut units = Vec::new();
+    for (n, start) in starts.iter().enumerate() {
+        let end = starts.get(n + 1).map_or(data.len(), |next| next - 3);
+        let mut unit = &data[*start..end];
+        // zero bytes before the next start code
+        while let [rest @ .., 0] = unit {
+            unit = rest;
+        }
+        if !unit.is_empty() {
+            units.push(unit);
+        }
+    }
+    units
+}
+/// keeps the first parameter set, the stream can not change them
+fn parameter_set(current: &mut Option<Vec<u8>>, nal: &[u8]) -> Res<()> {
+    match current {
+        Some(set) if set != nal => Err("video format changed during the recording".into()),
+        Some(_) => Ok(()),
+        None => {
+            *current = Some(nal.to_vec());
+            Ok(())
+        }
+    }
+}
+/// reads exp-golomb coded values of a nal unit without its emulation prevention bytes
+struct Bits {
+    data: Vec<u8>,
+    pos: usize,
+}
+impl Bits {
+    fn new(nal: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(nal.len());
+        let mut zeros = 0;
+        for b in nal {
+            if zeros >= 2 && *b == 3 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if *b == 0 { zeros + 1 } else { 0 };
+            data.push(*b);
+        }
+        Self { data, pos: 0 }
+    }
+    fn bit(&mut self) -> Option<u32> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = byte >> (7 - self.pos % 8) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        (0..n).try_fold(0, |v, _| Some(v << 1 | self.bit()?))
+    }
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u64 << zeros) as u32 - 1 + self.bits(zeros)?)
+    }
+    fn se(&mut self) -> Option<i32> {
+        let v = self.ue()? as i64;
+        Some(if v % 2 == 1 { (v + 1) / 2 } else { -v / 2 } as i32)
+    }
+}
+/// width and height of an sps
+fn dimensions(sps: &[u8]) -> Option<(u32, u32)> {
+    let mut bits = Bits::new(sps);
+    bits.bits(8)?;
+    let profile = bits.bits(8)?;
+    bits.bits(16)?;
+    bits.ue()?;
+    let mut chroma_format = 1;
+    if matches!(
+        profile,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format = bits.ue()?;
+        if chroma_format == 3 {
+            bits.bit()?;
+        }
+        bits.ue()?;
+        bits.ue()?;
+        bits.bit()?;
+        // scaling matrices
+        if bits.bit()? == 1 {
+            for i in 0..if chroma_format == 3 { 12 } else { 8 } {
+                if bits.bit()? == 0 {
+                    continue;
+                }
+                let size = if i < 6 { 16 } else { 64 };
+                let (mut last, mut next) = (8i32, 8i32);
+                for _ in 0..size {
+                    if next != 0 {
+                        next = (last + bits.se()? + 256) % 256;
+                    }
+                    last = if next == 0 { last } else { next };
+                }
+            }
+        }
+    }
+    bits.ue()?;
+    match bits.ue()? {
+        0 => {
+            bits.ue()?;
+        }
+        1 => {
+            bits.bit()?;
+            bits.se()?;
+            bits.se()?;
+            for _ in 0..bits.ue()? {
+                bits.se()?;
+            }
+        }
+        _ => {}
+    }
+    bits.ue()?;
+    bits.bit()?;
+    let width_mbs = bits.ue()? + 1;
+    let height_units = bits.ue()? + 1;
+    let frame_mbs_only = bits.bit()?;
+    if frame_mbs_only == 0 {
+        bits.bit()?;
+    }
+    bits.bit()?;
+    let (mut width, mut height) = (width_mbs * 16, (2 - frame_mbs_only) * height_units * 16);
+    if bits.bit()? == 1 {
+        let (left, right, top, bottom) = (bits.ue()?, bits.ue()?, bits.ue()?, bits.ue()?);
+        let (x, y) = match chroma_format {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        width = width.checked_sub(x * (left + right))?;
+        height = height.checked_sub(y * (2 - frame_mbs_only) * (top + bottom))?;
+    }
+    Some((width, height))
+}
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::remux::tests::TempFile};
+    /// adts header of a 48 khz stereo aac lc frame
+    fn adts(payload: usize) -> Vec<u8> {
+        let len = payload + 7;
+        vec![
+            0xFF,
+            0xF1,
+            1 << 6 | 3 << 2,
+            2 << 6 | (len >> 11) as u8,
+            (len >> 3) as u8,
+            (len as u8 & 7) << 5 | 0x1F,
+            0xFC,
+        ]
+    }
+    #[test]
+    fn timeline_across_wraps() {
+        let wrap = 1i64 << 33;
+        let mut timeline = Timeline::default();
+        let times: Vec<i64> = [wrap - 6000, wrap - 3000, 0, 3000]
+            .into_iter()
+            .map(|raw| timeline.map(raw, 3000))
+            .collect();
+        assert_eq!(times, [wrap - 6000, wrap - 3000, wrap, wrap + 3000]);
+        // more than a minute ahead continues after the last time too
+        assert_eq!(timeline.map(3000 + 61 * CLOCK, 3000), wrap + 6000);
+        assert_eq!(timeline.map(6000 + 61 * CLOCK, 3000), wrap + 9000);
+        // a small step back, ex. a b-frame, stays increasing
+        assert_eq!(timeline.map(5000 + 61 * CLOCK, 3000), wrap + 9001);
+    }
+    #[test]
+    fn dimensions_of_sps() {
+        // baseline 1280x720
+        let sps = [0x67, 0x42, 0xC0, 0x1F, 0xF4, 0x02, 0x80, 0x2D, 0xC8];
+        assert_eq!(dimensions(&sps), Some((1280, 720)));
+        assert_eq!(dimensions(&sps[..5]), None);
+    }
+    #[test]
+    fn adts_frames_across_pes() {
+        let temp = TempFile::new("adts.mp4");
+        let out = io::BufWriter::new(fs::File::create(&temp.0).unwrap());
+        let mut converter = Converter::new(out, 0);
+        let mut stream = Vec::new();
+        for size in [100, 200, 300] {
+            stream.extend(adts(size));
+            stream.extend(vec![size as u8; size]);
+        }
+        // the second frame starts in the first pes and the third in the second
+        let frame_clock = AAC_FRAME * CLOCK / 48000;
+        let (first, rest) = stream.split_at(107 + 50);
+        let (second, third) = rest.split_at(157 + 100);
+        converter
+            .audio_pes(Pes {
+                pts: Some(CLOCK),
+                dts: None,
+                data: first,
+            })
+            .unwrap();
+        assert_eq!(converter.audio.samples.len(), 1);
+        converter
+            .audio_pes(Pes {
+                pts: Some(CLOCK + 2 * frame_clock),
+                dts: None,
+                data: second,
+            })
+            .unwrap();
+        assert_eq!(converter.audio.samples.len(), 2);
+        converter
+            .audio_pes(Pes {
+                pts: Some(CLOCK + 3 * frame_clock),
+                dts: None,
+                data: third,
+            })
+            .unwrap();
+        let samples: Vec<(u32, i64)> = converter
+            .audio
+            .samples
+            .iter()
+            .map(|s| (s.size, s.dts))
+            .collect();
+        assert_eq!(samples, [(100, 48000), (200, 49024), (300, 50048)]);
+        assert!(converter.audio_pending.0.is_empty());
+        converter.out.flush().unwrap();
+        let written = fs::read(&temp.0).unwrap();
+        assert_eq!(written.len(), 600);
+        assert!(written[100..300].iter().all(|b| *b == 200));
+    }
+}
@@ -0,0 +1,163 @@
+use {
+    crate::{
+        platforms::{Platform, RoomState},
+        s,
+    },
+    std::{
+        collections::HashMap,
+        path::PathBuf,
+        sync::{Arc, Mutex, RwLock},
+        *,
+    },
+};
+type Res<T> = Result<T, crate::err::Error>;
+/// what a model is doing
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Offline,
+    Online,
+    Recording,
+    Muxing,
+}
+/// state of a tracked model, served by the api
+#[derive(serde::Serialize, Clone)]
+pub struct ModelStatus {
+    pub platform: Platform,
+    pub username: String,
+    pub enabled: bool,
+    pub state: State,
+    /// room state last reported by the platform
+    pub room: Option<RoomState>,
+    /// stopped through the api, not recorded again until it goes offline
+    pub stopped: bool,
+    pub recording: Option<Recording>,
+    #[serde(skip)]
+    abort: Arc<RwLock<bool>>,
+}
+/// progress of the current recording
+#[derive(serde::Serialize, Clone)]
+pub struct Recording {
+    pub started: String,
+    /// file the recording is saved to once muxed
+    pub file: Option<PathBuf>,
+    pub bytes_written: u64,
+    pub segments: u64,
+    /// seconds of stream written
+    pub duration: f64,
+}
+static MODELS: Mutex<Option<HashMap<String, ModelStatus>>> = Mutex::new(None);
+/// runs f on the status map
+fn with<T>(f: impl FnOnce(&mut HashMap<String, ModelStatus>) -> T) -> Res<T> {
+    let mut models = MODELS.lock().map_err(s!())?;
+    Ok(f(models.get_or_insert_with(HashMap::new)))
+}
+pub fn key(platform: &Platform, username: &str) -> String {
+    format!("{:?}:{}", platform, username)
+}
+/// adds a model or updates its options
+pub fn track(
+    platform: &Platform,
+    username: &str,
+    enabled: bool,
+    abort: &Arc<RwLock<bool>>,
+) -> Res<()> {
+    with(|models| {
+        let status = models
+            .entry(key(platform, username))
+            .or_insert_with(|| ModelStatus {
+                platform: platform.clone(),
+                username: username.to_string(),
+                enabled,
+                state: State::Offline,
+                room: None,
+                stopped: false,
+                recording: None,
+                abort: abort.clone(),
+            });
+        status.enabled = enabled;
+        status.abort = abort.clone();
+    })
+}
+/// removes a model that is no longer tracked
+pub fn untrack(key: &str) -> Res<()> {
+    with(|models| models.remove(key)).map(|_| ())
+}
+pub fn set_state(key: &str, state: State) -> Res<()> {
+    with(|models| {
+        if let Some(status) = models.get_mut(key) {
+            status.state = state;
+            match state {
+                State::Offline => status.recording = None,
+                State::Recording if status.recording.is_none() => {
+                    status.recording = Some(Recording {
+                        started: chrono::Local::now().to_rfc3339(),
+                        file: None,
+                        bytes_written: 0,
+                        segments: 0,
+                        duration: 0.0,
+                    });
+                }
+                _ => (),
+            }
+        }
+    })
+}
+pub fn set_room(key: &str, room: RoomState) -> Res<()> {
+    with(|models| {
+        if let Some(status) = models.get_mut(key) {
+            status.room = Some(room);
+        }
+    })
+}
+/// room state last reported by the platform
+pub fn room(key: &str) -> Option<RoomState> {
+    with(|models| models.get(key)?.room).ok().flatten()
+}
+/// updates the progress of the current recording
+pub fn update_recording(key: &str, f: impl FnOnce(&mut Recording)) -> Res<()> {
+    with(|models| {
+        if let Some(recording) = models.get_mut(key).and_then(|s| s.recording.as_mut()) {
+            f(recording);
+        }
+    })
+}
+/// every tracked model, sorted by platform and username
+pub fn list() -> Res<Vec<ModelStatus>> {
+    let mut list: Vec<ModelStatus> = with(|models| models.values().cloned().collect())?;
+    list.sort_by_key(|s| key(&s.platform, &s.username));
+    Ok(list)
+}
+pub fn get(key: &str) -> Res<Option<ModelStatus>> {
+    with(|models| models.get(key).cloned())
+}
+/// the model went offline, it can be recorded again
+pub fn clear_stopped(key: &str) -> Res<()> {
+    with(|models| {
+        if let Some(status) = models.get_mut(key) {
+            status.stopped = false;
+        }
+    })
+}
+/// whether the model was stopped through the api this session
+pub fn is_stopped(key: &str) -> bool {
+    with(|models| models.get(key).is_some_and(|s| s.stopped)).unwrap_or_default()
+}
+/// stops the current recording of a model, returns false if it is not recording
+pub fn stop(key: &str) -> Res<bool> {
+    let abort = with(|models| {
+        let status = models.get_mut(key)?;
+        if !matches!(status.state, State::Recording | State::Muxing) {
+            return None;
+        }
+        status.stopped = true;
+        Some(status.abort.clone())
+    })?;
+    match abort {
+        Some(abort) => {
+            *abort.write().map_err(s!())? = true;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
@@ -2,27 +2,32 @@ use {
     crate::{
         abort,
         config::Settings,
-        debug_eprintln, e, h, muxer, o,
-        platforms::Platform,
-        s,
+        debug, e,
+        err::Error,
+        error, h, hls, info,
+        journal::{Chapter, Journal},
+        log, metrics, muxer, o,
+        platforms::{ModelOptions, Platform, RoomState, StreamInfo},
+        s, status, template,
         util::{self, ManagedFile},
+        warn,
     },
     std::{
-        io::{Seek, Write},
-        sync::{Arc, Mutex, RwLock, mpsc},
+        io::{Read, Seek, Write},
+        sync::{Arc, OnceLock, RwLock, mpsc},
         *,
     },
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
+type Res<T> = Result<T, crate::err::Error>;
 #[derive(Clone)]
 pub struct Playlist {
     pub platform: Platform,
     pub username: String,
+    pub options: ModelOptions,
     pub playlist_url: String,
     pub playlist_audio_url: Option<String>,
     pub playlist: Option<String>,
     pub playlist_audio: Option<String>,
-    current_stream: Option<Arc<RwLock<Stream>>>,
     abort: Arc<RwLock<bool>>,
     downloading: Arc<RwLock<bool>>,
     /// video header
@@ -30,11 +35,48 @@ pub struct Playlist {
     /// optional - for audio/video split streams
     pub mp4_header_audio: Option<Arc<Vec<u8>>>,
     pub settings: Arc<Settings>,
+    /// details of the stream written with the recording
+    pub info: StreamInfo,
+    /// ids of segments of playlists without a media sequence
+    pub segment_ids: SegmentIds,
+}
+/// running numbers of segments by uri, kept across polls of a playlist
+#[derive(Clone, Default)]
+pub struct SegmentIds {
+    ids: collections::HashMap<String, u64>,
+    next: u64,
+}
+impl SegmentIds {
+    /// id of the segment, a new one after the newest if the segment was not seen before
+    pub fn id(&mut self, segment: &hls::MediaSegment) -> u64 {
+        *self.ids.entry(Self::key(segment)).or_insert_with(|| {
+            self.next += 1;
+            self.next - 1
+        })
+    }
+    /// forgets segments that left the playlist
+    pub fn retain(&mut self, playlist: &hls::MediaPlaylist) {
+        let keys: collections::HashSet<String> = playlist.segments.iter().map(Self::key).collect();
+        self.ids.retain(|key, _| keys.contains(key));
+    }
+    /// segments of one file differ by their byte range
+    fn key(segment: &hls::MediaSegment) -> String {
+        match segment.byte_range {
+            Some(range) => format!("{}@{}", segment.uri, range.offset),
+            None => segment.uri.clone(),
+        }
+    }
+}
+/// the model a playlist records
+pub struct Target {
+    pub platform: Platform,
+    pub username: String,
+    /// options of this recording, max-duration is what remains of the session
+    pub options: ModelOptions,
 }
 impl Playlist {
     pub fn new(
-        platform: Platform,
-        username: String,
+        target: Target,
         playlist_url: String,
         playlist_audio_url: Option<String>,
         abort: Arc<RwLock<bool>>,
@@ -42,33 +84,98 @@ impl Playlist {
         settings: Arc<Settings>,
     ) -> Self {
         Playlist {
-            platform,
-            username,
+            platform: target.platform,
+            username: target.username,
+            options: target.options,
             playlist_url,
             playlist_audio_url,
             playlist: None,
             playlist_audio: None,
-            current_stream: None,
             abort,
             downloading,
             mp4_header: None,
             mp4_header_audio: None,
             settings,
+            info: StreamInfo::default(),
+            segment_ids: SegmentIds::default(),
+        }
+    }
+    /// name of the segment file in a url
+    pub fn segment_name(url: &str) -> &str {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        path.rsplit('/').next().unwrap_or(path)
+    }
+    /// parses the downloaded media playlist and fetches its mp4 header once
+    pub fn media(&mut self, audio: bool) -> Res<hls::MediaPlaylist> {
+        let (text, url) = if audio {
+            (
+                self.playlist_audio.as_ref().ok_or_else(o!())?,
+                self.playlist_audio_url.as_ref().ok_or_else(o!())?,
+            )
+        } else {
+            (self.playlist.as_ref().ok_or_else(o!())?, &self.playlist_url)
+        };
+        let media = hls::MediaPlaylist::parse(text, url);
+        if let Some(key) = media.segments.iter().find_map(|s| s.key.as_ref()) {
+            return Err(format!("{} encrypted segments are not supported", key.method).into());
+        }
+        let header = if audio {
+            &mut self.mp4_header_audio
+        } else {
+            &mut self.mp4_header
+        };
+        if header.is_none()
+            && let Some(map) = media.segments.iter().find_map(|s| s.map.as_ref())
+        {
+            let mut http_headers = self
+                .platform
+                .headers(&self.settings.user_agent)
+                .map_err(s!())?;
+            if let Some(range) = map.byte_range {
+                http_headers.insert("range".into(), range.header());
+            }
+            let data = util::get_retry_vec(&self.platform, &map.uri, 5, Some(&http_headers))
+                .map_err(s!())?;
+            *header = Some(Arc::new(data));
+        }
+        Ok(media)
+    }
+    /// "<prefix>_<username>_<date>" of the platform
+    pub fn filename(&self, media: &hls::MediaPlaylist) -> String {
+        format!(
+            "{}_{}_{}",
+            self.platform.filename_prefix(),
+            self.platform.recording_name(&self.username),
+            Self::date(media)
+        )
+    }
+    /// date for filenames, from the playlist's first EXT-X-PROGRAM-DATE-TIME or the current time
+    pub fn date(media: &hls::MediaPlaylist) -> String {
+        match media.start_date() {
+            Some(date) => date.format("%y-%m-%d_%H-%M").to_string(),
+            None => util::date(),
         }
     }
     /// updates downloaded playlist with url
     fn update_playlist(&mut self) -> Res<()> {
-        let headers = util::create_headers(serde_json::json!({
-            "user-agent": &self.settings.user_agent,
-            "referer": self.platform.referer(),
-
-        }))
-        .map_err(s!())?;
-        let playlist = util::get_retry(&self.playlist_url, 5, Some(&headers)).map_err(s!())?;
+        let headers = self
+            .platform
+            .headers(&self.settings.user_agent)
+            .map_err(s!())?;
+        // playlists are removed once the stream ends
+        let ended = |e: Error| match e.status() {
+            Some(403 | 404) => Error::Offline,
+            _ => e,
+        };
+        let playlist = util::get_retry(&self.platform, &self.playlist_url, 5, Some(&headers))
+            .map_err(ended)
+            .map_err(s!())?;
         self.playlist = Some(playlist);
         if let Some(playlist_audio_url) = &self.playlist_audio_url {
             let playlist_audio =
-                util::get_retry(playlist_audio_url, 5, Some(&headers)).map_err(s!())?;
+                util::get_retry(&self.platform, playlist_audio_url, 5, Some(&headers))
+                    .map_err(ended)
+                    .map_err(s!())?;
             self.playlist_audio = Some(playlist_audio);
         }
         Ok(())
@@ -76,323 +183,822 @@ impl Playlist {
     fn abort_get(&self) -> Res<bool> {
         Ok(*self.abort.read().map_err(s!())?)
     }
-    /// Main Playlist Loop
-    pub fn playlist(&mut self) -> Res<()> {
+    /// Main Playlist Loop, continues the recording of resume if the stream has not moved on
+    pub fn playlist(&mut self, mut resume: Option<Journal>) -> Res<()> {
         let d = self.downloading.clone();
         scopeguard::defer! {
             if let Ok(mut downloading) = d.write() {
                 *downloading = false;
             }
         }
-        let mut mux_thread: Option<thread::JoinHandle<Result<(), String>>> = None;
+        let key = status::key(&self.platform, &self.username);
+        status::set_state(&key, status::State::Recording).map_err(s!())?;
+        let mut mux_thread: Option<thread::JoinHandle<Res<()>>> = None;
+        let (segment_tx, segment_rx) = mpsc::channel::<Segment>();
+        let mut segment_rx = Some(segment_rx);
+        let buffer = util::Budget::new(self.settings.recording_buffer);
+        let mut previous: Option<Position> = None;
         let mut trys = 0;
+        let start = time::Instant::now();
         while !self.abort_get().map_err(s!())? && !abort::get().map_err(s!())? {
             if let Some(mux_thread) = mux_thread.as_ref() {
                 if mux_thread.is_finished() {
                     break;
                 }
             }
+            if let Some(max_duration) = self.options.max_duration
+                && start.elapsed() >= max_duration
+            {
+                info!(
+                    "reached maximum duration of {}",
+                    util::format_duration(max_duration)
+                );
+                break;
+            }
             if let Err(state) = self.update_playlist().map_err(s!()) {
-                debug_eprintln!("{}", state);
+                match state.root() {
+                    Error::Offline | Error::Paused(_) => debug!("{}", state),
+                    _ => warn!("{}", state),
+                }
                 break;
             }
             trys += 1;
             if trys > 20 {
                 break;
             }
-            for mut new_stream in self.parse_playlist() {
-                if let Some(current) = &self.current_stream {
-                    if new_stream <= *current.read().map_err(s!())? {
-                        continue;
-                    }
+            let mut new_streams = self.parse_playlist();
+            new_streams.sort_by_key(Stream::order);
+            let mut resumed = None;
+            if let (Some(first), Some(last)) = (new_streams.first(), new_streams.last())
+                && let Some(journal) = resume.take()
+            {
+                if journal.continues(
+                    first.order(),
+                    last.order(),
+                    self.mp4_header.as_deref().map(Vec::as_slice),
+                ) {
+                    info!("continuing recording");
+                    previous = Position::from_journal(&journal);
+                    resumed = Some(journal);
+                } else {
+                    journal.finalize_background();
+                }
+            }
+            for new_stream in new_streams {
+                if previous
+                    .as_ref()
+                    .is_some_and(|previous| new_stream.order() <= previous.order)
+                {
+                    continue;
                 }
                 trys = 0;
-                new_stream.index = match self.current_stream.as_ref() {
-                    Some(o) => o.read().map_err(s!())?.index + 1,
-                    None => 1,
-                };
-                let new_stream = Arc::new(RwLock::new(new_stream));
-                if let Some(current_stream) = self.current_stream.as_ref() {
-                    let mut guard = current_stream.write().map_err(s!())?;
-                    guard.next_stream = Some(new_stream.clone());
-                    guard.next_stream_yield_rx = None;
-                    let _ = guard.next_stream_yield_tx.send(());
+                // segments that left the playlist before they were seen
+                let gap = previous.as_ref().and_then(|p| p.gap_to(&new_stream));
+                if let Some(gap) = gap.as_ref() {
+                    warn!(
+                        "missed {} segments after segment {}",
+                        gap.missing, gap.after
+                    );
+                }
+                previous = Some(Position::of(&new_stream));
+                let mut segment = new_stream.start_download(&buffer, gap);
+                segment.journal = resumed.take();
+                if segment_tx.send(segment).is_err() {
+                    break;
                 }
-                let s: Arc<RwLock<Stream>> = new_stream.clone();
-                thread::spawn(move || {
-                    download(s).unwrap();
-                });
-                self.current_stream = Some(new_stream);
                 if mux_thread.is_none() {
                     let m = self.clone();
-                    mux_thread = Some(thread::spawn(move || m.mux_streams().map_err(s!())));
+                    let rx = segment_rx.take().ok_or_else(o!())?;
+                    let context = log::context();
+                    mux_thread = Some(thread::spawn(move || {
+                        let _scope = log::enter(context);
+                        m.mux_streams(rx).map_err(s!())
+                    }));
                 }
             }
             thread::sleep(time::Duration::from_secs(1));
         }
+        if let Some(journal) = resume {
+            journal.finalize_background();
+        }
         // set muxer to finish up
         *self.downloading.write().map_err(s!())? = false;
-        if let Some(current_stream) = self.current_stream.as_ref() {
-            let mut guard = current_stream.write().map_err(s!())?;
-            guard.next_stream_yield_rx = None;
-            let _ = guard.next_stream_yield_tx.send(());
-        }
+        drop(segment_tx);
         if let Some(mux_header) = mux_thread {
             mux_header.join().map_err(h!())?.map_err(s!())?;
         }
+        status::set_state(&key, status::State::Offline).map_err(s!())?;
         Ok(())
     }
+    /// where a recording starting at date is saved, from the output template
+    fn final_path(
+        &self,
+        date: Option<chrono::DateTime<chrono::FixedOffset>>,
+        part: u32,
+    ) -> path::PathBuf {
+        let platform_settings = self.settings.for_platform(&self.platform);
+        let fields = template::Fields {
+            root: self
+                .options
+                .output
+                .as_deref()
+                .or(platform_settings.output_root.as_deref()),
+            platform: &self.platform,
+            username: &self.username,
+            start: date.unwrap_or_else(|| chrono::Local::now().fixed_offset()),
+            part,
+        };
+        let template = platform_settings.output_template.as_deref();
+        match template::render(template.unwrap_or(template::DEFAULT), &fields) {
+            Ok(path) => path,
+            Err(e) => {
+                error!("{}, using the default", e);
+                template::render(template::DEFAULT, &fields).unwrap_or_default()
+            }
+        }
+    }
+    /// why a recording is not kept, if it is under the minimum duration or size
+    fn too_short(&self, duration: f64, bytes: u64) -> Option<String> {
+        let platform_settings = self.settings.for_platform(&self.platform);
+        if let Some(min_duration) = platform_settings.min_duration
+            && duration < min_duration.as_secs_f64()
+        {
+            return Some(format!(
+                "shorter than {}",
+                util::format_duration(min_duration)
+            ));
+        }
+        if let Some(min_size) = platform_settings.min_size
+            && bytes < min_size
+        {
+            return Some(format!("smaller than {}", util::format_size(min_size)));
+        }
+        None
+    }
     fn parse_playlist(&mut self) -> Vec<Stream> {
-        match self.platform.parse_playlist()(self) {
+        match self.platform.provider().parse_playlist(self) {
             Ok(r) => r,
             Err(e) => {
-                eprintln!("{}", e);
+                error!("{}", e);
                 Vec::new()
             }
         }
     }
-    fn mux_streams(mut self) -> Res<()> {
+    /// writes segments to temp files in order as they download, then muxes them
+    fn mux_streams(self, segments: mpsc::Receiver<Segment>) -> Res<()> {
+        let mut muxing = Vec::new();
+        let result = self.write_parts(segments, &mut muxing);
+        // parts muxed in the background are done before the recording is
+        for handle in muxing {
+            handle.join().map_err(h!())?;
+        }
+        result
+    }
+    /// writes the parts of the recording, parts that were split off are muxed by the threads in muxing
+    fn write_parts(
+        self,
+        segments: mpsc::Receiver<Segment>,
+        muxing: &mut Vec<thread::JoinHandle<()>>,
+    ) -> Res<()> {
+        let key = status::key(&self.platform, &self.username);
         let mux_id = util::unique_time().map_err(e!())?;
-        let mut stream = self.current_stream.take().ok_or_else(o!())?;
+        let mut pending = segments.recv().ok();
         let temp_dir = util::temp_dir().map_err(s!())?;
         util::create_dir(&temp_dir).map_err(e!())?;
-        // generate files from current stream and initializes it
+        let (split_duration, split_size) = self.settings.split(&self.platform, &self.options);
+        // generate files from current segment and initializes it
         let mut repeat = false;
-        'outer: loop {
-            let (mut filename, contains_audio_bool) = {
-                let stream_guard = stream.read().map_err(s!())?;
-                (
-                    stream_guard.filename.clone(),
-                    stream_guard.url_audio.is_some(),
-                )
-            };
-            if repeat {
-                filename = format!("{}_{}", filename, mux_id)
-            }
-            let mut file: ManagedFile =
-                ManagedFile::generate_filenames(&self.username, &filename, false).map_err(s!())?;
-            let mut file_audio_option = if contains_audio_bool {
-                let file = ManagedFile::generate_filenames(&self.username, &filename, true)
-                    .map_err(s!())?;
-                Some(file)
-            } else {
-                None
-            };
+        let mut part = 1;
+        'outer: while let Some(first) = pending.as_mut() {
             // determine if there is some space left in temp directory
-            if let Some(available) = util::available_space_for_path(&file.path) {
+            if let Some(available) = util::available_space_for_path(&temp_dir) {
                 if available < 1 << 27 {
                     thread::sleep(time::Duration::from_secs(1));
                     continue 'outer;
                 }
             }
-            'inner: loop {
-                // waits if current stream is still downloading
-                let stream_downloading_yield = stream.read().map_err(s!())?.muxer_yield_rx.clone();
-                if let Some(_yield) = stream_downloading_yield {
-                    let _ = _yield.lock().map_err(s!())?.recv();
+            // continues the files of a recovered recording
+            let resumed = match first.journal.take() {
+                Some(journal) => journal.open().map_err(s!())?.map(|files| (files, journal)),
+                None => None,
+            };
+            let (mut file, mut file_audio_option, mut journal) = match resumed {
+                Some(((file, file_audio), mut journal)) => {
+                    journal.chapter("resumed".into(), first.position.date);
+                    part = journal.part;
+                    (file, file_audio, journal)
+                }
+                None => {
+                    let mut filename = first.filename.clone();
+                    if part > 1 {
+                        filename = format!("{}_part{}", filename, part)
+                    } else if repeat {
+                        filename = format!("{}_{}", filename, mux_id)
+                    }
+                    let final_path = self.final_path(first.position.date, part);
+                    let file: ManagedFile =
+                        ManagedFile::generate_filenames(&filename, &final_path, false)
+                            .map_err(s!())?;
+                    let file_audio = if first.audio.is_some() {
+                        let file = ManagedFile::generate_filenames(&filename, &final_path, true)
+                            .map_err(s!())?;
+                        Some(file)
+                    } else {
+                        None
+                    };
+                    let journal =
+                        Journal::create(&self, &file, file_audio.as_ref(), part).map_err(s!())?;
+                    (file, file_audio, journal)
+                }
+            };
+            info!("recording {}", file.final_path.display());
+            let final_path = file.final_path.clone();
+            status::update_recording(&key, |r| r.file = Some(final_path)).map_err(s!())?;
+            // size of this part, a resumed file keeps its size
+            let mut part_bytes = file.file.stream_position().map_err(e!())?;
+            let mut split = false;
+            let mut room = status::room(&key);
+            while let Some(mut segment) = pending.take() {
+                if let Some(gap) = segment.gap.as_ref() {
+                    // skipped segments are taken to be as long as this one
+                    journal.missing += gap.missing as f64 * segment.position.duration;
+                }
+                if let Some(title) = segment.chapter(&mut room, status::room(&key)) {
+                    journal.chapter(title, segment.position.date);
                 }
-                // write video stream
-                let data_option = stream.read().map_err(s!())?.data.clone();
-                if let Some(data) = data_option {
-                    let pos = file.file.stream_position().map_err(e!())?;
-                    if let Err(e) = file.file.write_all(&data) {
-                        if e.kind() != io::ErrorKind::StorageFull {
-                            return Err(e).map_err(e!())?;
-                        }
+                // lets the segment download without waiting for the memory budget
+                segment
+                    .priority
+                    .store(true, sync::atomic::Ordering::Relaxed);
+                journal.gaps.extend(segment.gap.take());
+                let pos = file.file.stream_position().map_err(e!())?;
+                let pos_audio = match file_audio_option.as_mut() {
+                    Some(file_audio) => file_audio.file.stream_position().map_err(e!())?,
+                    None => 0,
+                };
+                let mut written = write_chunks(&mut file, &segment.video).map_err(s!())?;
+                if file.file.stream_position().map_err(e!())? == pos && written {
+                    warn!("failed to download segment {}", segment.position.order);
+                    journal.gaps.push(segment.position.failed());
+                    metrics::segment(&self.platform, false);
+                }
+                // write optional audiostream
+                if let Some(file_audio) = file_audio_option.as_mut()
+                    && let Some(audio) = segment.audio.as_ref()
+                    && written
+                {
+                    written = write_chunks(file_audio, audio).map_err(s!())?;
+                    if !written {
                         file.file.seek(io::SeekFrom::Start(pos)).map_err(e!())?;
                         file.file.set_len(pos).map_err(e!())?;
-                        break 'inner;
                     }
                 }
-                // write optional audiostream
-                if let Some(file_audio) = file_audio_option.as_mut() {
-                    let data_audio_option = stream.read().map_err(s!())?.data_audio.clone();
-                    if let Some(data_audio) = data_audio_option {
-                        let pos = file_audio.file.stream_position().map_err(e!())?;
-                        if let Err(e) = file_audio.file.write_all(&data_audio) {
-                            if e.kind() != io::ErrorKind::StorageFull {
-                                Err(e).map_err(e!())?
-                            }
-                            file_audio
-                                .file
-                                .seek(io::SeekFrom::Start(pos))
-                                .map_err(e!())?;
-                            file.file.set_len(pos).map_err(e!())?;
-                            break 'inner;
-                        }
+                if written {
+                    segment.position.record(&mut journal);
+                    journal.write().map_err(s!())?;
+                    let mut bytes = file.file.stream_position().map_err(e!())? - pos;
+                    if let Some(file_audio) = file_audio_option.as_mut() {
+                        bytes += file_audio.file.stream_position().map_err(e!())? - pos_audio;
                     }
+                    part_bytes += bytes;
+                    metrics::segment(&self.platform, true);
+                    metrics::bytes_written(&self.platform, bytes);
+                    status::update_recording(&key, |r| {
+                        r.bytes_written += bytes;
+                        r.segments += 1;
+                        r.duration += segment.position.duration;
+                    })
+                    .map_err(s!())?;
                 }
-                // gets next stream and quit if done
-                let next_stream_yield = stream.read().map_err(s!())?.next_stream_yield_rx.clone();
-                if let Some(next_stream_yield) = next_stream_yield {
-                    let _ = next_stream_yield
-                        .lock()
-                        .map_err(s!())?
-                        .recv_timeout(time::Duration::from_mins(1));
+                // gets next segment and quit if done
+                pending = segments.recv().ok();
+                if !written {
+                    break;
                 }
-                let next_stream = match stream.write().map_err(s!())?.next_stream.take() {
-                    Some(o) => o,
-                    None => break 'inner,
-                };
-                stream = next_stream;
-            }
-            repeat = true;
-            // disables audio if it failed to download
-            if let Some(file_audio) = file_audio_option.as_ref() {
-                if file_audio.path.metadata().map_err(e!())?.len() == 0 {
-                    file_audio_option = None;
+                // the next part starts at the pending segment, which begins with the mp4 header
+                if pending.is_some()
+                    && (split_size.is_some_and(|size| part_bytes >= size)
+                        || split_duration.is_some_and(|d| journal.duration >= d.as_secs_f64()))
+                {
+                    split = true;
+                    break;
                 }
             }
-            // skips muxing if nothing downloaded
-            if file.path.metadata().map_err(e!())?.len() != 0 {
-                muxer::muxer(file, file_audio_option, self.platform.clone()).map_err(s!())?;
+            // parts of a split recording are kept however short
+            let whole = part == 1 && !split;
+            if split || part > 1 {
+                part += 1;
+            } else {
+                repeat = true;
             }
-            if stream.read().map_err(s!())?.next_stream.is_none()
-                && !*self.downloading.read().map_err(s!())?
+            if split {
+                info!("starting part {}", part);
+                // muxed in the background so the next part is written without waiting
+                let context = log::context();
+                muxing.push(thread::spawn(move || {
+                    let _scope = log::enter(context);
+                    let finished =
+                        finish(file, file_audio_option, &journal).and_then(|_| journal.remove());
+                    if let Err(e) = finished {
+                        error!("{}", e);
+                    }
+                }));
+                continue;
+            }
+            if whole && let Some(reason) = self.too_short(journal.duration, part_bytes) {
+                info!("discarded {}, {}", file.final_path.display(), reason);
+                // dropping the files deletes them
+                drop(file);
+                drop(file_audio_option);
+                journal.remove().map_err(s!())?;
+                continue;
+            }
+            status::set_state(&key, status::State::Muxing).map_err(s!())?;
+            finish(file, file_audio_option, &journal).map_err(s!())?;
+            journal.remove().map_err(s!())?;
+            status::set_state(&key, status::State::Recording).map_err(s!())?;
+        }
+        Ok(())
+    }
+}
+/// muxes a recording's temp files and writes its gaps and metadata
+pub fn finish(
+    file: ManagedFile,
+    mut file_audio: Option<ManagedFile>,
+    journal: &Journal,
+) -> Res<()> {
+    // disables audio if it failed to download
+    if let Some(audio) = file_audio.as_ref()
+        && audio.path.metadata().map_err(e!())?.len() == 0
+    {
+        file_audio = None;
+    }
+    // skips muxing if nothing downloaded
+    if file.path.metadata().map_err(e!())?.len() != 0 {
+        let audio_split = file_audio.is_some();
+        let tags = muxer::Tags::of(journal);
+        let (filepath, muxer) = muxer::muxer(
+            file,
+            file_audio,
+            journal.platform.clone(),
+            &tags,
+            &journal.mux,
+        )
+        .map_err(s!())?;
+        info!("saved {}", filepath.display());
+        if !journal.gaps.is_empty() {
+            write_gaps(&filepath, &journal.gaps).map_err(s!())?;
+        }
+        write_metadata(&filepath, journal, audio_split, muxer).map_err(s!())?;
+    }
+    Ok(())
+}
+/// part of a downloaded file, reset discards everything sent before it
+enum Chunk {
+    Data {
+        bytes: Vec<u8>,
+        /// memory budget held until the bytes are written
+        _reserved: [util::Reservation; 2],
+    },
+    Shared(Arc<Vec<u8>>),
+    Reset,
+}
+/// writes the chunks of a file as they arrive, returns false if the disk is full
+fn write_chunks(file: &mut ManagedFile, chunks: &mpsc::Receiver<Chunk>) -> Res<bool> {
+    let pos = file.file.stream_position().map_err(e!())?;
+    let mut storage_full = false;
+    for chunk in chunks.iter() {
+        let data: &[u8] = match &chunk {
+            Chunk::Data { bytes, .. } => bytes,
+            Chunk::Shared(data) => data,
+            Chunk::Reset => {
+                file.file.seek(io::SeekFrom::Start(pos)).map_err(e!())?;
+                file.file.set_len(pos).map_err(e!())?;
+                continue;
+            }
+        };
+        if storage_full {
+            continue;
+        }
+        if let Err(e) = file.file.write_all(data) {
+            if e.kind() != io::ErrorKind::StorageFull {
+                return Err(e).map_err(e!())?;
+            }
+            file.file.seek(io::SeekFrom::Start(pos)).map_err(e!())?;
+            file.file.set_len(pos).map_err(e!())?;
+            storage_full = true;
+        }
+    }
+    Ok(!storage_full)
+}
+/// where a stream sits in the recording
+struct Position {
+    order: u64,
+    /// ordered by media sequence, so missing numbers are missing segments
+    sequenced: bool,
+    date: Option<chrono::DateTime<chrono::FixedOffset>>,
+    duration: f64,
+}
+impl Position {
+    fn of(stream: &Stream) -> Self {
+        Self {
+            order: stream.order(),
+            sequenced: stream.sequence.is_some(),
+            date: stream.date,
+            duration: stream.duration,
+        }
+    }
+    fn end(&self) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+        Some(self.date? + chrono::Duration::milliseconds((self.duration * 1000.0) as i64))
+    }
+    /// gap between this and the next stream, if segments were skipped
+    fn gap_to(&self, next: &Stream) -> Option<Gap> {
+        if !self.sequenced || next.sequence.is_none() || next.order() <= self.order + 1 {
+            return None;
+        }
+        Some(Gap {
+            after: self.order,
+            missing: next.order() - self.order - 1,
+            reason: "skipped".into(),
+            detected: chrono::Local::now().to_rfc3339(),
+            start: self.end().map(|d| d.to_rfc3339()),
+            end: next.date.map(|d| d.to_rfc3339()),
+        })
+    }
+    /// position of the last segment written to a journal
+    fn from_journal(journal: &Journal) -> Option<Self> {
+        Some(Self {
+            order: journal.last_segment?,
+            sequenced: journal.sequenced,
+            date: journal
+                .last_date
+                .as_deref()
+                .and_then(|d| chrono::DateTime::parse_from_rfc3339(d).ok()),
+            duration: journal.last_duration,
+        })
+    }
+    /// marks this as the last segment written
+    fn record(&self, journal: &mut Journal) {
+        journal.last_segment = Some(self.order);
+        journal.sequenced = self.sequenced;
+        journal.last_date = self.date.map(|d| d.to_rfc3339());
+        journal.last_duration = self.duration;
+        if journal.segments == 0 {
+            journal.first_date = self.date.map(|d| d.to_rfc3339());
+        }
+        journal.segments += 1;
+        journal.duration += self.duration;
+    }
+    /// gap left by this stream failing to download
+    fn failed(&self) -> Gap {
+        Gap {
+            after: self.order.saturating_sub(1),
+            missing: 1,
+            reason: "failed".into(),
+            detected: chrono::Local::now().to_rfc3339(),
+            start: self.date.map(|d| d.to_rfc3339()),
+            end: self.end().map(|d| d.to_rfc3339()),
+        }
+    }
+}
+/// missing part of a recording, written to a ".gaps.json" sidecar
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Gap {
+    /// last segment before the gap
+    after: u64,
+    /// number of missing segments
+    missing: u64,
+    /// "skipped" if the segments were never seen, "failed" if they did not download
+    reason: String,
+    /// wall clock time the gap was found
+    detected: String,
+    /// stream time of the gap from EXT-X-PROGRAM-DATE-TIME
+    start: Option<String>,
+    end: Option<String>,
+}
+/// writes the gaps of a recording next to it
+fn write_gaps(filepath: &path::Path, gaps: &[Gap]) -> Res<()> {
+    let json = serde_json::to_string_pretty(gaps).map_err(e!())?;
+    fs::write(filepath.with_extension("gaps.json"), json).map_err(e!())?;
+    Ok(())
+}
+/// details of a recording written to a ".json" sidecar for archive tools
+#[derive(serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Metadata<'a> {
+    platform: &'a Platform,
+    username: &'a str,
+    #[serde(flatten)]
+    info: &'a StreamInfo,
+    /// EXT-X-PROGRAM-DATE-TIME of the first segment, else when recording started
+    start: Option<String>,
+    /// end of the last segment from EXT-X-PROGRAM-DATE-TIME, else when it was muxed
+    end: Option<String>,
+    /// seconds of segments written
+    duration: f64,
+    segments: u64,
+    gaps: &'a [Gap],
+    chapters: &'a [Chapter],
+    /// audio was downloaded separately and muxed with the video
+    audio_split: bool,
+    /// "ffmpeg" or "local"
+    muxer: &'static str,
+    version: &'static str,
+}
+/// writes the details of a recording next to it
+fn write_metadata(
+    filepath: &path::Path,
+    journal: &Journal,
+    audio_split: bool,
+    muxer: &'static str,
+) -> Res<()> {
+    let end = Position::from_journal(journal).and_then(|p| p.end());
+    let metadata = Metadata {
+        platform: &journal.platform,
+        username: &journal.username,
+        info: &journal.info,
+        start: journal.start(),
+        end: Some(end.map_or_else(|| chrono::Local::now().to_rfc3339(), |d| d.to_rfc3339())),
+        duration: journal.duration,
+        segments: journal.segments,
+        gaps: &journal.gaps,
+        chapters: &journal.chapters,
+        audio_split,
+        muxer,
+        version: crate::TAG.unwrap_or_default(),
+    };
+    let json = serde_json::to_string_pretty(&metadata).map_err(e!())?;
+    fs::write(filepath.with_extension("json"), json).map_err(e!())?;
+    Ok(())
+}
+/// a stream being downloaded, handed to the muxer in playlist order
+struct Segment {
+    filename: String,
+    position: Position,
+    /// segments missing before this one
+    gap: Option<Gap>,
+    /// EXT-X-DISCONTINUITY before this segment
+    discontinuity: bool,
+    /// recovered recording this segment continues
+    journal: Option<Journal>,
+    video: mpsc::Receiver<Chunk>,
+    audio: Option<mpsc::Receiver<Chunk>>,
+    priority: Arc<sync::atomic::AtomicBool>,
+}
+impl Segment {
+    /// title of a chapter starting at this segment, for a discontinuity, gap or room change
+    fn chapter(&self, room: &mut Option<RoomState>, current: Option<RoomState>) -> Option<String> {
+        let mut titles = Vec::new();
+        if self.discontinuity {
+            titles.push("discontinuity".to_string());
+        }
+        if let Some(gap) = self.gap.as_ref() {
+            titles.push(format!("{} missing segments", gap.missing));
+        }
+        if current.is_some() && current != *room {
+            if room.is_some()
+                && let Some(current) = current
             {
-                break 'outer;
+                titles.push(current.to_string());
             }
+            *room = current;
         }
-        return Ok(());
+        (!titles.is_empty()).then(|| titles.join(", "))
     }
 }
+impl Drop for Segment {
+    fn drop(&mut self) {
+        // releases a download waiting on the budget if the muxer is gone
+        self.priority.store(true, sync::atomic::Ordering::Relaxed);
+    }
+}
+/// global memory budget shared by all recordings
+static MEMORY_BUDGET: OnceLock<Arc<util::Budget>> = OnceLock::new();
+pub fn memory_budget() -> Arc<util::Budget> {
+    MEMORY_BUDGET
+        .get_or_init(|| util::Budget::new(u64::MAX))
+        .clone()
+}
 pub struct Stream {
     pub filename: String,
     url: String,
+    range: Option<hls::ByteRange>,
     url_audio: Option<String>,
-    stream_id: u32,
-    index: u32,
-    data: Option<Arc<Vec<u8>>>,
-    data_audio: Option<Arc<Vec<u8>>>,
+    range_audio: Option<hls::ByteRange>,
+    stream_id: u64,
+    sequence: Option<u64>,
+    date: Option<chrono::DateTime<chrono::FixedOffset>>,
+    duration: f64,
+    discontinuity: bool,
     pub mp4_header: Option<Arc<Vec<u8>>>,
     pub mp4_header_audio: Option<Arc<Vec<u8>>>,
-    pub next_stream: Option<Arc<RwLock<Stream>>>,
     platform: Platform,
     user_agent: String,
-    muxer_yield_tx: mpsc::Sender<()>,
-    muxer_yield_rx: Option<Arc<Mutex<mpsc::Receiver<()>>>>,
-    next_stream_yield_tx: mpsc::Sender<()>,
-    next_stream_yield_rx: Option<Arc<Mutex<mpsc::Receiver<()>>>>,
 }
 impl Stream {
+    /// stream of a media segment, with an optional matching audio segment
     pub fn new(
+        playlist: &Playlist,
         filename: &str,
-        url: &str,
-        url_audio: Option<&str>,
-        id: u32,
-        platform: Platform,
-        user_agent: String,
-        mp4_header: Option<Arc<Vec<u8>>>,
-        mp4_header_audio: Option<Arc<Vec<u8>>>,
+        id: u64,
+        segment: &hls::MediaSegment,
+        segment_audio: Option<&hls::MediaSegment>,
     ) -> Self {
-        let url_audio = if url_audio.is_none() {
-            None
-        } else {
-            Some(url_audio.unwrap().to_string())
-        };
-        let (tx, rx) = mpsc::channel();
-        let muxer_yield_tx = tx;
-        let muxer_yield_rx = Some(Arc::new(Mutex::new(rx)));
-        let (tx, rx) = mpsc::channel();
-        let next_stream_yield_tx = tx;
-        let next_stream_yield_rx = Some(Arc::new(Mutex::new(rx)));
         Self {
             filename: filename.to_string(),
-            url: url.to_string(),
-            url_audio: url_audio,
+            url: segment.uri.clone(),
+            range: segment.byte_range,
+            url_audio: segment_audio.map(|s| s.uri.clone()),
+            range_audio: segment_audio.and_then(|s| s.byte_range),
             stream_id: id,
-            index: 0,
-            data: None,
-            data_audio: None,
-            mp4_header,
-            mp4_header_audio,
-            next_stream: None,
-            platform,
-            user_agent,
-            muxer_yield_tx,
-            muxer_yield_rx,
-            next_stream_yield_tx,
-            next_stream_yield_rx,
+            sequence: segment.sequence,
+            date: segment.program_date_time,
+            duration: segment.duration,
+            discontinuity: segment.discontinuity,
+            mp4_header: playlist.mp4_header.clone(),
+            mp4_header_audio: segment_audio.and(playlist.mp4_header_audio.clone()),
+            platform: playlist.platform.clone(),
+            user_agent: playlist.settings.user_agent.clone(),
         }
     }
-}
-/// downloads the stream given the Stream's url
-fn download(stream_lock: Arc<RwLock<Stream>>) -> Res<()> {
-    let stream_defer = stream_lock.clone();
-    scopeguard::defer! {
-        if let Ok(mut stream) = stream_defer.write().map_err(s!()) {
-            stream.muxer_yield_rx = None;
-            let _ = stream.muxer_yield_tx.send(());
-        }
+
+    /// position in the recording, the media sequence where available, else the id from the filename
+    fn order(&self) -> u64 {
+        self.sequence.unwrap_or(self.stream_id)
     }
-    // quick read to not lock stream
-    let stream = stream_lock.read().map_err(s!())?;
-    let filename = stream.filename.clone();
-    let stream_id = stream.stream_id.clone();
-    let referer = stream.platform.referer();
-    let url_video = stream.url.clone();
-    let url_audio = stream.url_audio.clone();
-    let user_agent = stream.user_agent.clone();
-    let mp4_header = stream.mp4_header.clone();
-    let mp4_header_audio = stream.mp4_header_audio.clone();
-    drop(stream);
-    println!("{}_{}", filename, stream_id);
-    let headers = util::create_headers(serde_json::json!({
-        "user-agent": user_agent,
-        "referer": referer,
-    }))
-    .map_err(s!())?;
-    let mut video_data: Vec<u8> =
-        match util::get_retry_vec(&url_video, 5, Some(&headers)).map_err(s!()) {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("{}:{}", e, &url_video);
-                return Ok(());
+    /// starts downloading the stream in the background
+    fn start_download(self, buffer: &Arc<util::Budget>, gap: Option<Gap>) -> Segment {
+        let (video_tx, video_rx) = mpsc::sync_channel(CHUNK_QUEUE);
+        let (audio_tx, audio_rx) = match self.url_audio.is_some() {
+            true => {
+                let (tx, rx) = mpsc::sync_channel(CHUNK_QUEUE);
+                (Some(tx), Some(rx))
             }
+            false => (None, None),
         };
-    if video_data.len() < 10000 {
-        debug_eprintln!("{}", String::from_utf8_lossy(&video_data));
-        return Ok(());
-    }
-    if let Some(mp4_header) = mp4_header {
-        let mut video_combined = (*mp4_header).clone();
-        video_combined.append(&mut video_data);
-        video_data = video_combined;
-    }
-    let mut audio_data: Option<Arc<Vec<u8>>> = None;
-    'audio: {
-        if let Some(url_audio) = &url_audio {
-            let mut audio_data_internal: Vec<u8> =
-                match util::get_retry_vec(url_audio, 5, Some(&headers)).map_err(s!()) {
-                    Ok(r) => r,
-                    Err(e) => {
-                        eprintln!("{}:{}", e, url_audio);
-                        Vec::new()
-                    }
-                };
-            if audio_data_internal.len() == 0 {
-                break 'audio;
-            };
-            if let Some(mp4_header_audio) = mp4_header_audio {
-                let mut audio_combined = (*mp4_header_audio).clone();
-                audio_combined.append(&mut audio_data_internal);
-                audio_data_internal = audio_combined;
+        let segment = Segment {
+            filename: self.filename.clone(),
+            position: Position::of(&self),
+            gap,
+            discontinuity: self.discontinuity,
+            journal: None,
+            video: video_rx,
+            audio: audio_rx,
+            priority: Arc::new(sync::atomic::AtomicBool::new(false)),
+        };
+        let budgets = [buffer.clone(), memory_budget()];
+        let priority = segment.priority.clone();
+        let context = log::context();
+        thread::spawn(move || {
+            let _scope = log::enter(context);
+            if let Err(e) = self.download(video_tx, audio_tx, &budgets, &priority) {
+                error!("{}", e);
             }
-            audio_data = Some(Arc::new(audio_data_internal));
+        });
+        segment
+    }
+    /// downloads the stream given the Stream's url
+    fn download(
+        self,
+        video_tx: mpsc::SyncSender<Chunk>,
+        audio_tx: Option<mpsc::SyncSender<Chunk>>,
+        budgets: &[Arc<util::Budget>; 2],
+        priority: &sync::atomic::AtomicBool,
+    ) -> Res<()> {
+        debug!("{}_{}", self.filename, self.stream_id);
+        let headers = self.platform.headers(&self.user_agent).map_err(s!())?;
+        let fetch = Fetch {
+            platform: &self.platform,
+            headers: &headers,
+            budgets,
+            priority,
+        };
+        let downloaded = fetch
+            .retry(&self.url, self.range, self.mp4_header.as_ref(), &video_tx)
+            .map_err(s!())?;
+        drop(video_tx);
+        if let (true, Some(url_audio), Some(audio_tx)) = (downloaded, &self.url_audio, audio_tx) {
+            fetch
+                .retry(
+                    url_audio,
+                    self.range_audio,
+                    self.mp4_header_audio.as_ref(),
+                    &audio_tx,
+                )
+                .map_err(s!())?;
         }
+        Ok(())
     }
-    let stream = &mut *stream_lock.write().map_err(s!())?;
-    stream.data = Some(Arc::new(video_data));
-    stream.data_audio = audio_data;
-    Ok(())
 }
-impl PartialEq for Stream {
-    fn eq(&self, other: &Self) -> bool {
-        self.stream_id == other.stream_id
-    }
+/// number of chunks queued per file before the download waits on the muxer
+const CHUNK_QUEUE: usize = 16;
+const CHUNK_SIZE: usize = 1 << 16;
+/// files under this size are error pages, not segments
+const MIN_SEGMENT_SIZE: usize = 10000;
+/// streams a file into a chunk channel, keeping memory within the budgets
+struct Fetch<'a> {
+    platform: &'a Platform,
+    headers: &'a collections::HashMap<String, String>,
+    budgets: &'a [Arc<util::Budget>; 2],
+    priority: &'a sync::atomic::AtomicBool,
 }
-impl PartialOrd for Stream {
-    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        Some(self.stream_id.cmp(&other.stream_id))
+impl Fetch<'_> {
+    /// downloads url with retries, returns false if nothing was downloaded
+    fn retry(
+        &self,
+        url: &str,
+        range: Option<hls::ByteRange>,
+        header: Option<&Arc<Vec<u8>>>,
+        tx: &mpsc::SyncSender<Chunk>,
+    ) -> Res<bool> {
+        let result = util::retry(5, || match self.fetch(url, range, header, tx) {
+            Ok(downloaded) => Ok(downloaded),
+            Err(FetchError::Closed) => Ok(false),
+            Err(FetchError::Failed(e, sent)) => {
+                // the muxer drops what was sent of the failed attempt
+                if sent && tx.send(Chunk::Reset).is_err() {
+                    return Ok(false);
+                }
+                Err(e)
+            }
+        });
+        match result {
+            Ok(r) => Ok(r),
+            Err(e) => {
+                warn!("{}:{}", e, url);
+                Ok(false)
+            }
+        }
     }
+    fn fetch(
+        &self,
+        url: &str,
+        range: Option<hls::ByteRange>,
+        header: Option<&Arc<Vec<u8>>>,
+        tx: &mpsc::SyncSender<Chunk>,
+    ) -> Result<bool, FetchError> {
+        let failed = |e: Error, sent| FetchError::Failed(e, sent);
+        let mut headers = self.headers.clone();
+        if let Some(range) = range {
+            headers.insert("range".into(), range.header());
+        }
+        let mut resp =
+            util::get_response(self.platform, url, Some(&headers)).map_err(|e| failed(e, false))?;
+        // hold back the start of the body until it is large enough to be a segment
+        let mut data: Vec<u8> = Vec::new();
+        let mut sent = false;
+        loop {
+            let mut buf = vec![0u8; CHUNK_SIZE];
+            let n = match resp.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(failed(e.into(), sent)),
+            };
+            buf.truncate(n);
+            if data.is_empty() {
+                data = buf;
+            } else {
+                data.append(&mut buf);
+            }
+            if !sent && n != 0 && data.len() < MIN_SEGMENT_SIZE {
+                continue;
+            }
+            if !sent {
+                if data.len() < MIN_SEGMENT_SIZE {
+                    debug!("{}", String::from_utf8_lossy(&data));
+                    return Ok(false);
+                }
+                if let Some(header) = header {
+                    tx.send(Chunk::Shared(header.clone()))
+                        .map_err(|_| FetchError::Closed)?;
+                }
+                sent = true;
+            }
+            if !data.is_empty() {
+                let size = data.len() as u64;
+                let reserved = [
+                    self.budgets[0].acquire(size, self.priority),
+                    self.budgets[1].acquire(size, self.priority),
+                ];
+                let reserved = match reserved {
+                    [Ok(a), Ok(b)] => [a, b],
+                    [Err(e), _] | [_, Err(e)] => return Err(failed(e, sent)),
+                };
+                let chunk = Chunk::Data {
+                    bytes: mem::take(&mut data),
+                    _reserved: reserved,
+                };
+                tx.send(chunk).map_err(|_| FetchError::Closed)?;
+            }
+            if n == 0 {
+                return Ok(true);
+            }
+        }
+    }
+}
+enum FetchError {
+    /// the muxer stopped reading
+    Closed,
+    /// download failed, with whether part of it was already sent
+    Failed(Error, bool),
 }
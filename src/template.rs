@@ -0,0 +1,84 @@
+use {
+    crate::platforms::Platform,
+    std::{
+        fmt::Write,
+        path::{Path, PathBuf},
+        *,
+    },
+};
+type Res<T> = Result<T, crate::err::Error>;
+/// "<root>/<username>/<prefix>_<username>_<date>", the layout before templates
+pub const DEFAULT: &str = "{root}/{username}/{prefix}_{username}_{start:%y-%m-%d_%H-%M}";
+const DATE_FORMAT: &str = "%y-%m-%d_%H-%M";
+/// values filled into an output template
+pub struct Fields<'a> {
+    /// output folder, the working directory if unset
+    pub root: Option<&'a Path>,
+    pub platform: &'a Platform,
+    pub username: &'a str,
+    /// date of the first segment, or when the recording started
+    pub start: chrono::DateTime<chrono::FixedOffset>,
+    /// counted from 1 when a recording is split
+    pub part: u32,
+}
+/// final path of a recording without its extension, the muxer adds it
+/// "{ext}" can only end the template, parts are numbered "_part<n>" if "{part}" is not used
+pub fn render(template: &str, fields: &Fields) -> Res<PathBuf> {
+    let template = template.strip_suffix(".{ext}").unwrap_or(template);
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        rendered.push_str(&rest[..open]);
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed {{ in output template {}", template))?;
+        let field = &rest[open + 1..open + close];
+        let (name, format) = match field.split_once(':') {
+            Some((name, format)) => (name, Some(format)),
+            None => (field, None),
+        };
+        match name {
+            "root" => match fields.root {
+                Some(root) => rendered.push_str(&root.to_string_lossy()),
+                None => rendered.push('.'),
+            },
+            "platform" => rendered.push_str(fields.platform.name()),
+            "prefix" => rendered.push_str(fields.platform.filename_prefix()),
+            "username" => {
+                let name = fields.platform.recording_name(fields.username);
+                rendered.push_str(&sanitize(&name));
+            }
+            "start" => {
+                // writing instead of to_string, invalid formats are an error not a panic
+                let format = format.unwrap_or(DATE_FORMAT);
+                write!(rendered, "{}", fields.start.format(format))?;
+            }
+            "part" => rendered.push_str(&fields.part.to_string()),
+            _ => return Err(format!("unknown field {{{}}} in output template", field).into()),
+        }
+        rest = &rest[open + close + 1..];
+    }
+    rendered.push_str(rest);
+    if fields.part > 1 && !template.contains("{part}") {
+        rendered = format!("{}_part{}", rendered, fields.part);
+    }
+    let path = PathBuf::from(rendered);
+    match path.strip_prefix(".") {
+        Ok(relative) => Ok(relative.to_path_buf()),
+        Err(_) => Ok(path),
+    }
+}
+/// keeps letters, digits, "-" and "_" so a name is one valid path component on every system
+pub fn sanitize(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| match c.is_alphanumeric() || c == '-' || c == '_' {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    match sanitized.is_empty() {
+        true => "_".into(),
+        false => sanitized,
+    }
+}
@@ -1,92 +1,68 @@
 use {
-    crate::{e, o, s},
+    crate::{e, err::Error, error, http, metrics, o, platforms::Platform, s},
     std::{
         collections::HashMap,
         path::{Path, PathBuf},
+        str::FromStr,
+        sync::{Arc, Condvar, Mutex, atomic},
         *,
     },
 };
-type Res<T> = Result<T, Box<dyn error::Error>>;
+type Res<T> = Result<T, crate::err::Error>;
 
-pub fn get_retry(url: &str, trys: i32, headers: Option<&HashMap<String, String>>) -> Res<String> {
+pub fn get_retry(
+    platform: &Platform,
+    url: &str,
+    trys: i32,
+    headers: Option<&HashMap<String, String>>,
+) -> Res<String> {
     let f = || {
-        let client = reqwest::blocking::Client::new();
-        let build = if let Some(headers) = headers {
-            let mut h = reqwest::header::HeaderMap::new();
-            use reqwest::header::HeaderName;
-            use reqwest::header::HeaderValue;
-            use std::str::FromStr;
-            for (k, v) in headers {
-                h.insert(
-                    HeaderName::from_str(&k).map_err(e!())?,
-                    HeaderValue::from_str(&v).map_err(e!())?,
-                );
-            }
-            client.get(url).headers(h)
-        } else {
-            client.get(url)
-        };
-        let resp = build.send().map_err(e!())?;
+        let build = request(reqwest::Method::GET, platform, url, headers).map_err(s!())?;
+        let resp = send(platform, url, build).map_err(s!())?;
         let status = resp.status();
-        let mut text = resp.text().map_err(e!())?;
+        let text = resp.text().map_err(s!())?;
         if status != 200 {
-            text.truncate(100);
-            return Err(format!("{}-{}", status, text))?;
+            return Err(status_error(status, text)).map_err(s!());
         }
         Ok(text)
     };
-    let mut r = Err("".into());
-    for _ in 0..trys {
-        r = f();
-        if r.is_ok() {
-            break;
-        }
-        thread::sleep(time::Duration::from_millis(250));
-    }
-    r
+    retry(trys, f)
 }
 pub fn get_retry_vec(
+    platform: &Platform,
     url: &str,
     trys: i32,
     headers: Option<&HashMap<String, String>>,
 ) -> Res<Vec<u8>> {
     let f = |url| {
-        let client = reqwest::blocking::Client::new();
-        let build = if let Some(headers) = headers {
-            let mut h = reqwest::header::HeaderMap::new();
-            use reqwest::header::HeaderName;
-            use reqwest::header::HeaderValue;
-            use std::str::FromStr;
-            for (k, v) in headers {
-                h.insert(
-                    HeaderName::from_str(&k).map_err(e!())?,
-                    HeaderValue::from_str(&v).map_err(e!())?,
-                );
-            }
-            client.get(url).headers(h)
-        } else {
-            client.get(url)
-        };
-        let resp = build.send().map_err(e!())?;
+        let build = request(reqwest::Method::GET, platform, url, headers).map_err(s!())?;
+        let resp = send(platform, url, build).map_err(s!())?;
         let status = resp.status();
-        if status != 200 {
-            let mut text = resp.text().map_err(e!())?;
-            text.truncate(100);
-            return Err(format!("{}-{}", status, text))?;
+        if !status.is_success() {
+            let text = resp.text().map_err(s!())?;
+            return Err(status_error(status, text)).map_err(s!());
         }
-        Ok(resp.bytes().map_err(e!())?.to_vec())
+        resp.bytes().map_err(s!())
     };
-    let mut r = Err("".into());
-    for _ in 0..trys {
-        r = f(url);
-        if r.is_ok() {
-            break;
-        }
-        thread::sleep(time::Duration::from_millis(250));
+    retry(trys, || f(url))
+}
+/// sends a get request and returns the response for reading the body incrementally
+pub fn get_response(
+    platform: &Platform,
+    url: &str,
+    headers: Option<&HashMap<String, String>>,
+) -> Res<Response> {
+    let build = request(reqwest::Method::GET, platform, url, headers).map_err(s!())?;
+    let resp = send(platform, url, build).map_err(s!())?;
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().map_err(s!())?;
+        return Err(status_error(status, text)).map_err(s!());
     }
-    r
+    Ok(resp)
 }
 pub fn post_retry(
+    platform: &Platform,
     url: &str,
     trys: i32,
     headers: Option<&HashMap<String, String>>,
@@ -94,45 +70,125 @@ pub fn post_retry(
     content_type: &str,
 ) -> Res<String> {
     let f = || {
-        let client = reqwest::blocking::Client::new();
-        let build = if let Some(headers) = headers {
-            let mut h = reqwest::header::HeaderMap::new();
-            use reqwest::header::HeaderName;
-            use reqwest::header::HeaderValue;
-            use std::str::FromStr;
-            for (k, v) in headers {
-                h.insert(
-                    HeaderName::from_str(&k).map_err(e!())?,
-                    HeaderValue::from_str(&v).map_err(e!())?,
-                );
-            }
-            client.post(url).headers(h)
-        } else {
-            client.post(url)
-        };
-        let resp = build
+        let build = request(reqwest::Method::POST, platform, url, headers).map_err(s!())?;
+        let build = build
             .body(payload.to_string())
-            .header("content-type", content_type)
-            .send()
-            .map_err(e!())?;
+            .header("content-type", content_type);
+        let resp = send(platform, url, build).map_err(s!())?;
         let status = resp.status();
-        let mut text = resp.text().map_err(e!())?;
+        let text = resp.text().map_err(s!())?;
         if status != 200 {
-            text.truncate(100);
-            return Err(format!("{}-{}", status, text))?;
+            return Err(status_error(status, text)).map_err(s!());
         }
         Ok(text)
     };
+    retry(trys, f)
+}
+/// request builder with the client of the platform and the headers
+fn request(
+    method: reqwest::Method,
+    platform: &Platform,
+    url: &str,
+    headers: Option<&HashMap<String, String>>,
+) -> Res<reqwest::blocking::RequestBuilder> {
+    let client = http::client(platform).map_err(s!())?;
+    let mut map = reqwest::header::HeaderMap::new();
+    for (k, v) in headers.into_iter().flatten() {
+        map.insert(
+            reqwest::header::HeaderName::from_str(k).map_err(e!())?,
+            reqwest::header::HeaderValue::from_str(v).map_err(e!())?,
+        );
+    }
+    Ok(client.request(method, url).headers(map))
+}
+/// calls f up to trys times while it fails in a way retrying can help, waiting longer each time
+pub fn retry<T>(trys: i32, mut f: impl FnMut() -> Res<T>) -> Res<T> {
     let mut r = Err("".into());
-    for _ in 0..trys {
+    for attempt in 0..trys {
         r = f();
-        if r.is_ok() {
-            break;
+        match &r {
+            Err(e) if is_retryable(e) && attempt + 1 < trys => thread::sleep(backoff(attempt)),
+            _ => break,
         }
-        thread::sleep(time::Duration::from_millis(250));
     }
     r
 }
+/// network errors, timeouts, rate limits and server errors
+fn is_retryable(e: &Error) -> bool {
+    match e.root() {
+        Error::Network(_) => true,
+        // reads of a response body fail with the request's error
+        Error::Io(e) => {
+            e.get_ref()
+                .is_some_and(|inner| inner.is::<reqwest::Error>())
+                || matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::UnexpectedEof
+                )
+        }
+        Error::Http { status, .. } => matches!(status, 408 | 429 | 500..),
+        _ => false,
+    }
+}
+/// 250ms doubling with each attempt up to 8s, randomly shortened by up to half
+fn backoff(attempt: i32) -> time::Duration {
+    let delay = time::Duration::from_millis(250) * 2u32.pow(attempt.clamp(0, 5) as u32);
+    delay.mul_f64(1.0 - random() / 2.0)
+}
+/// error for an unsuccessful response, with the start of its body
+fn status_error(status: reqwest::StatusCode, mut text: String) -> Error {
+    // 100 characters, a byte count can end inside one
+    if let Some((end, _)) = text.char_indices().nth(100) {
+        text.truncate(end);
+    }
+    match status.as_u16() {
+        451 => Error::GeoBlocked,
+        status => Error::Http { status, body: text },
+    }
+}
+/// response that holds its request slot until the body is read or it is dropped
+pub struct Response {
+    inner: reqwest::blocking::Response,
+    _permit: http::Permit,
+}
+impl Response {
+    pub fn status(&self) -> reqwest::StatusCode {
+        self.inner.status()
+    }
+    pub fn text(self) -> Res<String> {
+        self.inner.text().map_err(e!())
+    }
+    pub fn bytes(self) -> Res<Vec<u8>> {
+        Ok(self.inner.bytes().map_err(e!())?.to_vec())
+    }
+}
+impl io::Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+/// sends a request within the limits of its platform or host and counts it in the metrics
+fn send(platform: &Platform, url: &str, build: reqwest::blocking::RequestBuilder) -> Res<Response> {
+    let permit = http::acquire(platform, url).map_err(s!())?;
+    match build.send() {
+        Ok(resp) => {
+            metrics::http_request(platform, resp.status().as_str());
+            permit.finish(Some(&resp));
+            Ok(Response {
+                inner: resp,
+                _permit: permit,
+            })
+        }
+        Err(e) => {
+            metrics::http_request(platform, "error");
+            permit.finish(None);
+            Err(e).map_err(e!())?
+        }
+    }
+}
 pub fn create_headers(json_map: serde_json::Value) -> Res<HashMap<String, String>> {
     let mut headers: HashMap<String, String> = HashMap::new();
     for (k, v) in json_map.as_object().ok_or_else(o!())? {
@@ -145,6 +201,18 @@ pub fn date() -> String {
     let now = chrono::Local::now();
     now.format("%y-%m-%d_%H-%M").to_string()
 }
+/// random number from 0 to 1
+pub fn random() -> f64 {
+    use hash::{BuildHasher, Hasher};
+    let mut hasher = hash::RandomState::new().build_hasher();
+    hasher.write_u128(
+        time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish() as f64 / u64::MAX as f64
+}
 /// returns current hour and miniute appended together
 pub fn unique_time() -> Res<String> {
     let sec_from_epoch = time::SystemTime::now()
@@ -195,6 +263,166 @@ pub fn url_prefix<'a>(url: &'a str, suffix: &str) -> Option<&'a str> {
         url.get(..n)
     }
 }
+/// parses durations like "4h", "1h30m", "90s" or plain seconds
+pub fn parse_duration(text: &str) -> Res<time::Duration> {
+    let text = text.trim();
+    if let Ok(seconds) = text.parse::<u64>() {
+        return Ok(time::Duration::from_secs(seconds));
+    }
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("invalid duration unit '{}' in \"{}\"", c, text).into()),
+        };
+        seconds += number.parse::<u64>().map_err(e!())? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("missing duration unit in \"{}\"", text).into());
+    }
+    Ok(time::Duration::from_secs(seconds))
+}
+/// formats a duration in the format read by parse_duration
+pub fn format_duration(duration: time::Duration) -> String {
+    let seconds = duration.as_secs();
+    let parts = [
+        (seconds / 3600, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+    let text: String = parts
+        .iter()
+        .filter(|(n, _)| *n != 0)
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .collect();
+    if text.is_empty() { "0s".into() } else { text }
+}
+/// parses sizes like "512MB", "1.5G" or plain bytes, units are powers of 1024
+pub fn parse_size(text: &str) -> Res<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number = number.parse::<f64>().map_err(e!())?;
+    let unit = match unit
+        .trim()
+        .to_uppercase()
+        .trim_end_matches("IB")
+        .trim_end_matches('B')
+    {
+        "" => 1u64,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("invalid size unit in \"{}\"", text).into()),
+    };
+    Ok((number * unit as f64) as u64)
+}
+/// formats a size in the format read by parse_size
+pub fn format_size(size: u64) -> String {
+    for (unit, n) in [
+        ("TB", 1u64 << 40),
+        ("GB", 1 << 30),
+        ("MB", 1 << 20),
+        ("KB", 1 << 10),
+    ] {
+        if size >= n && size.is_multiple_of(n) {
+            return format!("{}{}", size / n, unit);
+        }
+    }
+    size.to_string()
+}
+/// serde helpers for sizes written as "512MB"
+pub mod serde_size {
+    pub fn serialize<S>(size: &u64, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&super::format_size(*size))
+    }
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<u64, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::Deserialize;
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(b) => Ok(b),
+            Raw::Text(t) => {
+                super::parse_size(&t).map_err(|e| serde::de::Error::custom(e.to_string()))
+            }
+        }
+    }
+}
+/// serde helpers for optional sizes written as "4GB"
+pub mod serde_option_size {
+    pub fn serialize<S>(size: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match size {
+            Some(size) => super::serde_size::serialize(size, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::Deserialize;
+        #[derive(Deserialize)]
+        struct Size(#[serde(with = "super::serde_size")] u64);
+        Ok(Option::<Size>::deserialize(deserializer)?.map(|Size(size)| size))
+    }
+}
+/// serde helpers for optional durations written as "4h"
+pub mod serde_duration {
+    use std::time::Duration;
+    pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match duration {
+            Some(d) => serializer.serialize_str(&super::format_duration(*d)),
+            None => serializer.serialize_none(),
+        }
+    }
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::Deserialize;
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Seconds(u64),
+            Text(String),
+        }
+        match Option::<Raw>::deserialize(deserializer)? {
+            None => Ok(None),
+            Some(Raw::Seconds(s)) => Ok(Some(Duration::from_secs(s))),
+            Some(Raw::Text(t)) => super::parse_duration(&t)
+                .map(Some)
+                .map_err(|e| serde::de::Error::custom(e.to_string())),
+        }
+    }
+}
 pub fn remove_non_num(url: &str) -> String {
     url.chars()
         .filter(|c| c.is_ascii_digit())
@@ -205,14 +433,20 @@ pub struct ManagedFile {
     pub path: PathBuf,
     pub final_path: PathBuf,
 }
-fn create_valid_path(path: &Path) -> Res<PathBuf> {
-    let mut path = path.to_path_buf();
-    if path.exists() {
-        let stem = path.file_stem().ok_or_else(o!())?.to_string_lossy();
-        let ext = path.extension().ok_or_else(o!())?.to_string_lossy();
-        path.set_file_name(format!("{}-.{}", stem, ext));
+/// path, or the first of "<name>_2.<ext>", "<name>_3.<ext>" ... that does not exist
+pub fn create_valid_path(path: &Path) -> Res<PathBuf> {
+    if !path.exists() {
+        return Ok(path.to_path_buf());
     }
-    Ok(path)
+    let stem = path.file_stem().ok_or_else(o!())?.to_string_lossy();
+    let ext = match path.extension() {
+        Some(ext) => format!(".{}", ext.to_string_lossy()),
+        None => String::new(),
+    };
+    (2..)
+        .map(|n| path.with_file_name(format!("{}_{}{}", stem, n, ext)))
+        .find(|path| !path.exists())
+        .ok_or_else(o!())
 }
 impl ManagedFile {
     pub fn new(path: PathBuf, final_path: PathBuf) -> Res<Self> {
@@ -227,30 +461,29 @@ impl ManagedFile {
             final_path,
         })
     }
-    pub fn mv(&self, final_path: &Path) -> Res<()> {
+    /// moves the file to final_path, returns the path it was moved to
+    pub fn mv(&self, final_path: &Path) -> Res<PathBuf> {
         let final_path = create_valid_path(final_path).map_err(s!())?;
         match fs::rename(&self.path, &final_path) {
-            Ok(_) => Ok(()),
+            Ok(_) => Ok(final_path),
             Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
                 fs::copy(&self.path, &final_path)?;
-                Ok(())
+                Ok(final_path)
             }
             Err(e) => Err(e).map_err(s!())?,
         }
     }
-    pub fn generate_filenames(username: &str, filename: &str, audio: bool) -> Res<Self> {
-        let temp_dir = temp_dir().map_err(s!())?;
-        let mut filepath = PathBuf::from(&temp_dir);
-        let mut final_filepath = PathBuf::from(username);
+    /// temp file named filename that is moved to final_path once muxed
+    pub fn generate_filenames(filename: &str, final_path: &Path, audio: bool) -> Res<Self> {
+        let mut filepath = temp_dir().map_err(s!())?;
         if !audio {
-            filepath.push(&filename);
+            filepath.push(filename);
         } else {
             filepath.push(format!("{}_audio", filename));
         }
-        final_filepath.push(&filename);
         let file = Self::new(
             create_valid_path(&filepath).map_err(s!())?,
-            create_valid_path(&final_filepath).map_err(s!())?,
+            final_path.to_path_buf(),
         )
         .map_err(s!())?;
         Ok(file)
@@ -260,7 +493,7 @@ impl Drop for ManagedFile {
     fn drop(&mut self) {
         if self.path.exists() {
             match fs::remove_file(&self.path).map_err(e!()) {
-                Err(e) => eprintln!("{}", e),
+                Err(e) => error!("{}", e),
                 _ => (),
             };
         }
@@ -275,3 +508,57 @@ pub fn available_space_for_path(path: &PathBuf) -> Option<u64> {
         .max_by_key(|d| d.mount_point().as_os_str().len())
         .map(|d| d.available_space())
 }
+/// limits the bytes held in memory by downloads
+pub struct Budget {
+    state: Mutex<BudgetState>,
+    freed: Condvar,
+}
+struct BudgetState {
+    limit: u64,
+    used: u64,
+}
+/// bytes taken from a budget, given back on drop
+pub struct Reservation {
+    budget: Arc<Budget>,
+    size: u64,
+}
+impl Budget {
+    pub fn new(limit: u64) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(BudgetState { limit, used: 0 }),
+            freed: Condvar::new(),
+        })
+    }
+    pub fn set_limit(&self, limit: u64) -> Res<()> {
+        self.state.lock().map_err(s!())?.limit = limit;
+        self.freed.notify_all();
+        Ok(())
+    }
+    /// waits until size bytes fit in the budget, skips waiting once priority is set
+    pub fn acquire(self: &Arc<Self>, size: u64, priority: &atomic::AtomicBool) -> Res<Reservation> {
+        let mut state = self.state.lock().map_err(s!())?;
+        while state.used != 0
+            && state.used + size > state.limit
+            && !priority.load(atomic::Ordering::Relaxed)
+        {
+            state = self
+                .freed
+                .wait_timeout(state, time::Duration::from_millis(100))
+                .map_err(s!())?
+                .0;
+        }
+        state.used += size;
+        Ok(Reservation {
+            budget: self.clone(),
+            size,
+        })
+    }
+}
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.budget.state.lock() {
+            state.used = state.used.saturating_sub(self.size);
+        }
+        self.budget.freed.notify_all();
+    }
+}